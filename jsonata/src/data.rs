@@ -1,3 +1,15 @@
+use jsonata_error::Result;
+use jsonata_expression::Expression;
+
+use crate::environment::Environment;
+
+/// A step from a value to one of its children.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Field(String),
+    Index(usize),
+}
+
 pub trait JsonataData {
     fn get_field(&self, field: &str) -> Option<Self>
         where 
@@ -15,6 +27,48 @@ pub trait JsonataData {
 
     fn from_array(array: Vec<Self>) -> Self
         where Self: Sized;
+
+    fn as_object(&self) -> Option<Vec<(String, Self)>>
+        where Self: Sized;
+
+    fn from_object(entries: Vec<(String, Self)>) -> Self
+        where Self: Sized;
+
+    fn as_bool(&self) -> Option<bool>;
+
+    fn from_bool(value: bool) -> Self
+        where Self: Sized;
+
+    fn as_str(&self) -> Option<&str>;
+
+    fn from_string(value: String) -> Self
+        where Self: Sized;
+
+    fn is_null(&self) -> bool;
+
+    fn null() -> Self
+        where Self: Sized;
+
+    /// A copy that shares no structure with `self`, so it can be modified on its own.
+    fn deep_clone(&self) -> Self
+        where Self: Sized;
+
+    /// Calls `f` with the value at `location` inside `self`, if there is a value there.
+    fn modify(&mut self, location: &[Segment], f: &mut dyn FnMut(&mut Self) -> Result<()>) -> Result<()>
+        where Self: Sized;
+
+    /// Sets the fields of an object, replacing those it already has.
+    fn merge(&mut self, entries: Vec<(String, Self)>)
+        where Self: Sized;
+
+    fn remove_field(&mut self, field: &str);
+
+    /// Applies `| pattern | update, delete |` to a copy of `input`.
+    fn transform(pattern: &Expression, update: &Expression, delete: Option<&Expression>, input: &Self, root: &Self, environment: &Environment<Self>) -> Result<Self>
+        where Self: Sized + Clone + 'static
+    {
+        crate::transform::transform(pattern, update, delete, input, root, environment)
+    }
 }
 
 
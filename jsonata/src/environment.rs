@@ -1,36 +1,216 @@
 use jsonata_error::Result;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::JsonataData;
 
 pub struct Function<T> {
     // arity: usize,
     // pub args: Vec<T>,
-    pub implementation: Box<dyn Fn(Vec<T>) -> Result<T>>, 
+    pub implementation: Box<dyn Fn(Vec<T>) -> Result<T>>,
 }
-    
+
 
 pub enum Binding<T> {
     Value(T),
     Function(Function<T>)
 }
 
+/// The implementation of a function value, undefined arguments and results are `None`.
+pub type Implementation<T> = dyn Fn(Vec<Option<Value<T>>>) -> Result<Option<Value<T>>>;
+
+/// A function value: a host function, a builtin or a lambda with the environment it was defined in.
+pub struct Callable<T> {
+    /// The number of parameters, if known.
+    pub arity: Option<usize>,
+    pub implementation: Box<Implementation<T>>,
+}
+
+/// What an expression evaluates to, JSON data or a function.
+#[derive(Clone)]
+pub enum Value<T> {
+    Data(T),
+    Function(Rc<Callable<T>>),
+}
+
+impl<T> Value<T> {
+    /// The value as data, functions have no JSON representation and are undefined.
+    pub fn data(self) -> Option<T> {
+        match self {
+            Value::Data(data) => Some(data),
+            Value::Function(_) => None,
+        }
+    }
+}
+
+/// The contexts a path navigated from, innermost first, for the parent operator.
+struct Ancestry<T> {
+    context: T,
+    outer: Option<Rc<Ancestry<T>>>,
+}
+
+struct Scope<T> {
+    bindings: RefCell<HashMap<String, Option<Value<T>>>>,
+    outer: Option<Environment<T>>,
+    ancestry: Option<Rc<Ancestry<T>>>,
+    // The evaluation this scope belongs to, none for the environment owned by `Jsonata`
+    captures: Weak<Captures<T>>,
+    // Whether the scope is in `captures` already
+    captured: Cell<bool>,
+}
 
+/// The scopes captured by the functions defined during an evaluation.
+///
+/// A function keeps the scope it was defined in, so binding it in that scope, or in one of
+/// the scopes around it, is a reference cycle. The bindings of the captured scopes are cleared
+/// when the evaluation ends, which breaks these cycles. Results are data, so no function can
+/// be called after that.
+pub struct Captures<T> {
+    scopes: RefCell<Vec<Rc<Scope<T>>>>,
+}
+
+impl<T> Captures<T> {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Captures {
+            scopes: RefCell::new(vec![]),
+        })
+    }
+}
+
+impl<T> Drop for Captures<T> {
+    fn drop(&mut self) {
+        for scope in self.scopes.take() {
+            scope.bindings.take();
+        }
+    }
+}
+
+/// The variables in scope. Cloning an environment shares its bindings, so lambdas can keep
+/// the environment they were defined in.
 pub struct Environment<T> {
-    bindings: HashMap<String, Binding<T>>
+    scope: Rc<Scope<T>>,
+}
+
+impl<T> Clone for Environment<T> {
+    fn clone(&self) -> Self {
+        Environment {
+            scope: Rc::clone(&self.scope),
+        }
+    }
 }
 
-impl<T> Environment<T>
+impl<T: JsonataData + Clone + 'static> Environment<T>
 {
     pub fn new() -> Self {
         Environment {
-            bindings: HashMap::new()
+            scope: Rc::new(Scope {
+                bindings: RefCell::new(HashMap::new()),
+                outer: None,
+                ancestry: None,
+                captures: Weak::new(),
+                captured: Cell::new(false),
+            }),
+        }
+    }
+
+    fn child(&self, ancestry: Option<Rc<Ancestry<T>>>) -> Self {
+        self.child_capturing(ancestry, self.scope.captures.clone())
+    }
+
+    fn child_capturing(&self, ancestry: Option<Rc<Ancestry<T>>>, captures: Weak<Captures<T>>) -> Self {
+        Environment {
+            scope: Rc::new(Scope {
+                bindings: RefCell::new(HashMap::new()),
+                outer: Some(self.clone()),
+                ancestry,
+                captures,
+                captured: Cell::new(false),
+            }),
+        }
+    }
+
+    /// A child scope for an evaluation, the scopes its functions capture are kept by `captures`.
+    pub fn evaluation(&self, captures: &Rc<Captures<T>>) -> Self {
+        self.child_capturing(self.scope.ancestry.clone(), Rc::downgrade(captures))
+    }
+
+    /// This environment for a function defined in it to keep, see `Captures`.
+    pub fn capture(&self) -> Self {
+        // The scopes around a captured scope are captured with it
+        let mut scope = Some(&self.scope);
+        while let Some(current) = scope.filter(|scope| !scope.captured.get()) {
+            let Some(captures) = current.captures.upgrade() else {
+                break;
+            };
+            current.captured.set(true);
+            captures.scopes.borrow_mut().push(Rc::clone(current));
+            scope = current.outer.as_ref().map(|outer| &outer.scope);
+        }
+        self.clone()
+    }
+
+    /// A child scope, its bindings shadow the bindings of this environment.
+    pub fn extend(&self) -> Self {
+        self.child(self.scope.ancestry.clone())
+    }
+
+    /// A child scope in which `context` is the parent.
+    pub fn with_parent(&self, context: T) -> Self {
+        let outer = self.scope.ancestry.clone();
+        self.child(Some(Rc::new(Ancestry { context, outer })))
+    }
+
+    /// A child scope in which the parent of the current parent is the parent.
+    pub fn without_parent(&self) -> Self {
+        let ancestry = self.scope.ancestry.as_ref().and_then(|ancestry| ancestry.outer.clone());
+        self.child(ancestry)
+    }
+
+    /// The context the current path step navigated from.
+    pub fn parent(&self) -> Option<T> {
+        self.scope.ancestry.as_ref().map(|ancestry| ancestry.context.clone())
+    }
+
+    pub fn bind(&self, name: String, binding: Binding<T>) {
+        let value = match binding {
+            Binding::Value(value) => Value::Data(value),
+            Binding::Function(Function { implementation }) => Value::Function(Rc::new(Callable {
+                arity: None,
+                implementation: Box::new(move |args| {
+                    let args = args
+                        .into_iter()
+                        .map(|arg| arg.and_then(Value::data).unwrap_or_else(T::null))
+                        .collect();
+                    implementation(args).map(|result| Some(Value::Data(result)))
+                }),
+            })),
+        };
+        self.bind_value(name, Some(value));
+    }
+
+    /// Binds a name in this scope, an undefined value still shadows outer bindings.
+    pub fn bind_value(&self, name: String, value: Option<Value<T>>) {
+        self.scope.bindings.borrow_mut().insert(name, value);
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Value<T>> {
+        if let Some(value) = self.scope.bindings.borrow().get(name) {
+            return value.clone();
         }
+        self.scope.outer.as_ref().and_then(|outer| outer.lookup(name))
     }
 
-    pub fn bind(&mut self, name: String, binding: Binding<T>) {
-        self.bindings.insert(name, binding);
+    /// The variables in scope and their values.
+    pub fn variables(&self) -> HashMap<String, Option<Value<T>>> {
+        let mut variables = self.scope.outer.as_ref().map(Environment::variables).unwrap_or_default();
+        variables.extend(self.scope.bindings.borrow().iter().map(|(name, value)| (name.clone(), value.clone())));
+        variables
     }
 
-    pub fn lookup(&self, name: &String) -> Option<&Binding<T>> {
-        self.bindings.get(name)
+    /// The number of references to this scope.
+    #[cfg(test)]
+    pub fn references(&self) -> usize {
+        Rc::strong_count(&self.scope)
     }
 }
@@ -1,11 +1,98 @@
-use jsonata_expression::{Atom, Expression, NumericBinaryOperator};
+use jsonata_expression::{Atom, BooleanBinaryOperator, ComparisonOperator, Expression, NumericBinaryOperator, NumericUnaryOperator, SortOrder};
 use jsonata_error::{Result, Error};
 
-use crate::{environment::{Binding, Environment, Function}, JsonataData};
+use std::cmp::Ordering;
+use std::rc::Rc;
 
-fn evalute_numeric_binary<T: JsonataData + Clone>(op: &NumericBinaryOperator, lhs: &Expression, rhs: &Expression, data: &T, environment: &Environment<T>) -> Result<T> {
-    let lhs = evaluate(lhs, data, environment)?.as_f64().ok_or(Error::T2001)?;
-    let rhs = evaluate(rhs, data, environment)?.as_f64().ok_or(Error::T2002)?;
+use crate::{environment::{Callable, Environment, Value}, JsonataData};
+
+// Evaluation results are `None` where JSONata yields `undefined`,
+// e.g. a path that does not match anything in the input.
+
+/// The items of a sequence, a value that is not an array is a sequence of one.
+pub(crate) fn sequence<T: JsonataData + Clone>(value: T) -> Vec<T> {
+    match value.as_array() {
+        Some(items) => items,
+        None => vec![value],
+    }
+}
+
+/// An empty sequence is undefined and a sequence of one is the item itself.
+pub(crate) fn collapse<T: JsonataData + Clone>(mut items: Vec<T>) -> Option<T> {
+    match items.len() {
+        0 => None,
+        1 => items.pop(),
+        _ => Some(T::from_array(items)),
+    }
+}
+
+/// Flattens the results of a step into a single sequence.
+fn flatten<T: JsonataData + Clone>(results: Vec<T>) -> Option<T> {
+    collapse(results.into_iter().flat_map(sequence).collect())
+}
+
+/// Looks up a field, mapping over the items if the input is an array.
+fn lookup<T: JsonataData + Clone>(data: &T, name: &str) -> Option<T> {
+    match data.as_array() {
+        Some(items) => flatten(items.iter().filter_map(|item| lookup(item, name)).collect()),
+        None => data.get_field(name),
+    }
+}
+
+/// Appends a value to a sequence, flattening nested arrays.
+fn append_flattened<T: JsonataData + Clone>(results: &mut Vec<T>, value: T) {
+    match value.as_array() {
+        Some(items) => items.into_iter().for_each(|item| append_flattened(results, item)),
+        None => results.push(value),
+    }
+}
+
+/// The values of the fields of an object, or the items of an array, flattened.
+fn wildcard<T: JsonataData + Clone>(data: &T) -> Option<T> {
+    let mut results = vec![];
+    if let Some(object) = data.as_object() {
+        object.into_iter().for_each(|(_, value)| append_flattened(&mut results, value));
+    } else if let Some(items) = data.as_array() {
+        items.into_iter().for_each(|item| append_flattened(&mut results, item));
+    }
+    collapse(results)
+}
+
+/// Collects a value and everything nested in it, arrays themselves are not included.
+fn descendants<T: JsonataData + Clone>(data: &T, results: &mut Vec<T>) {
+    if let Some(items) = data.as_array() {
+        items.iter().for_each(|item| descendants(item, results));
+        return;
+    }
+    results.push(data.clone());
+    if let Some(object) = data.as_object() {
+        object.iter().for_each(|(_, value)| descendants(value, results));
+    }
+}
+
+/// The environment to evaluate against the results of a path step in, where the parent
+/// operator refers to the context the step navigated from.
+fn step_environment<T: JsonataData + Clone + 'static>(step: &Expression, data: &T, environment: &Environment<T>) -> Environment<T> {
+    match step {
+        Expression::Atom(Atom::Name(_)) | Expression::Wildcard => environment.with_parent(data.clone()),
+        Expression::Parent(_) => environment.without_parent(),
+        Expression::Predicate(step, _) | Expression::Index(step, _) | Expression::Focus(step, _) => step_environment(step, data, environment),
+        _ => environment.clone(),
+    }
+}
+
+fn evalute_numeric_binary<T: JsonataData + Clone + 'static>(op: &NumericBinaryOperator, lhs: &Expression, rhs: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    let lhs = match evaluate(lhs, data, root, environment)? {
+        Some(lhs) => Some(lhs.as_f64().ok_or(Error::T2001)?),
+        None => None,
+    };
+    let rhs = match evaluate(rhs, data, root, environment)? {
+        Some(rhs) => Some(rhs.as_f64().ok_or(Error::T2002)?),
+        None => None,
+    };
+    let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+        return Ok(None);
+    };
     let res = match op {
         NumericBinaryOperator::Add => lhs + rhs,
         NumericBinaryOperator::Mul => lhs * rhs,
@@ -13,63 +100,629 @@ fn evalute_numeric_binary<T: JsonataData + Clone>(op: &NumericBinaryOperator, lh
         NumericBinaryOperator::Div => lhs / rhs,
         NumericBinaryOperator::Mod => lhs % rhs,
     };
-    Ok(T::from_f64(res))
+    Ok(Some(T::from_f64(res)))
 }
 
-pub fn evaluate<T: JsonataData + Clone>(expr: &Expression, data: &T, environment: &Environment<T>) -> Result<T> {
-    match expr {
-        Expression::Atom(Atom::Number(n)) => Ok(T::from_f64(*n)),
-        Expression::Atom(Atom::Name(n)) => {
-            match data.get_field(n) {
-                Some(n) => Ok(n),
-                None => todo!("What should happend here?, on name: {}", n)
+/// Casts a value to a boolean following the JSONata `$boolean` rules.
+fn boolean<T: JsonataData>(value: &T) -> bool {
+    if let Some(b) = value.as_bool() {
+        b
+    } else if let Some(n) = value.as_f64() {
+        n != 0.0
+    } else if let Some(s) = value.as_str() {
+        !s.is_empty()
+    } else if let Some(array) = value.as_array() {
+        array.iter().any(boolean)
+    } else if let Some(object) = value.as_object() {
+        !object.is_empty()
+    } else {
+        !value.is_null()
+    }
+}
+
+/// Formats a number the way JSONata does, with 15 significant digits.
+fn format_number(n: f64) -> String {
+    let n: f64 = format!("{n:.14e}").parse().unwrap_or(n);
+    n.to_string()
+}
+
+/// Serializes a value as JSON.
+fn json<T: JsonataData>(value: &T) -> String {
+    if let Some(s) = value.as_str() {
+        let mut quoted = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+                c => quoted.push(c),
             }
+        }
+        quoted.push('"');
+        quoted
+    } else if let Some(array) = value.as_array() {
+        format!("[{}]", array.iter().map(json).collect::<Vec<_>>().join(","))
+    } else if let Some(object) = value.as_object() {
+        let entries: Vec<String> = object.iter()
+            .map(|(key, value)| format!("{}:{}", json(&T::from_string(key.clone())), json(value)))
+            .collect();
+        format!("{{{}}}", entries.join(","))
+    } else {
+        string(value)
+    }
+}
+
+/// Casts a value to a string following the JSONata `$string` rules.
+fn string<T: JsonataData>(value: &T) -> String {
+    if let Some(s) = value.as_str() {
+        s.to_string()
+    } else if let Some(n) = value.as_f64() {
+        format_number(n)
+    } else if let Some(b) = value.as_bool() {
+        b.to_string()
+    } else if value.is_null() {
+        "null".to_string()
+    } else {
+        json(value)
+    }
+}
+
+fn evaluate_concatenate<T: JsonataData + Clone + 'static>(lhs: &Expression, rhs: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    let lhs = evaluate(lhs, data, root, environment)?.map(|value| string(&value)).unwrap_or_default();
+    let rhs = evaluate(rhs, data, root, environment)?.map(|value| string(&value)).unwrap_or_default();
+    Ok(Some(T::from_string(lhs + &rhs)))
+}
+
+/// Groups the items by the key expressions and evaluates each value expression once per group.
+/// A standalone object constructor groups the context, a group-by the result of its path.
+fn evaluate_group<T: JsonataData + Clone + 'static>(pairs: &[(Expression, Expression)], input: Option<T>, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    // Without input the keys are still evaluated, so literal objects can be built
+    let items = input.map(sequence).unwrap_or_else(|| vec![T::null()]);
+    let mut groups: Vec<(String, Vec<T>, usize)> = vec![];
+    for item in items {
+        for (index, (key, _)) in pairs.iter().enumerate() {
+            let Some(key) = evaluate(key, &item, root, environment)? else {
+                continue;
+            };
+            let key = key.as_str().ok_or(Error::T1003)?;
+            match groups.iter_mut().find(|(k, _, _)| k == key) {
+                Some((_, _, i)) if *i != index => return Err(Error::D1009),
+                Some((_, group, _)) => group.push(item.clone()),
+                None => groups.push((key.to_string(), vec![item.clone()], index)),
+            }
+        }
+    }
+    let mut entries = vec![];
+    for (key, group, index) in groups {
+        let Some(context) = flatten(group) else {
+            continue;
+        };
+        if let Some(value) = evaluate(&pairs[index].1, &context, root, environment)? {
+            entries.push((key, value));
+        }
+    }
+    Ok(Some(T::from_object(entries)))
+}
+
+fn evaluate_boolean_binary<T: JsonataData + Clone + 'static>(op: &BooleanBinaryOperator, lhs: &Expression, rhs: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    let truthy = |expr| -> Result<bool> {
+        Ok(evaluate(expr, data, root, environment)?.is_some_and(|value| boolean(&value)))
+    };
+    let res = match op {
+        BooleanBinaryOperator::And => truthy(lhs)? && truthy(rhs)?,
+        BooleanBinaryOperator::Or => truthy(lhs)? || truthy(rhs)?,
+    };
+    Ok(Some(T::from_bool(res)))
+}
+
+fn deep_equal<T: JsonataData>(lhs: &T, rhs: &T) -> bool {
+    if let (Some(lhs), Some(rhs)) = (lhs.as_f64(), rhs.as_f64()) {
+        lhs == rhs
+    } else if let (Some(lhs), Some(rhs)) = (lhs.as_str(), rhs.as_str()) {
+        lhs == rhs
+    } else if let (Some(lhs), Some(rhs)) = (lhs.as_bool(), rhs.as_bool()) {
+        lhs == rhs
+    } else if let (Some(lhs), Some(rhs)) = (lhs.as_array(), rhs.as_array()) {
+        lhs.len() == rhs.len() && lhs.iter().zip(rhs.iter()).all(|(lhs, rhs)| deep_equal(lhs, rhs))
+    } else if let (Some(lhs), Some(rhs)) = (lhs.as_object(), rhs.as_object()) {
+        lhs.len() == rhs.len() && lhs.iter().all(|(key, lhs)| {
+            rhs.iter().any(|(k, rhs)| k == key && deep_equal(lhs, rhs))
+        })
+    } else {
+        lhs.is_null() && rhs.is_null()
+    }
+}
+
+fn evaluate_comparison<T: JsonataData + Clone + 'static>(op: &ComparisonOperator, lhs: &Expression, rhs: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    let lhs = evaluate(lhs, data, root, environment)?;
+    let rhs = evaluate(rhs, data, root, environment)?;
+    let res = match op {
+        ComparisonOperator::Equal | ComparisonOperator::NotEqual => {
+            let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+                return Ok(Some(T::from_bool(false)));
+            };
+            deep_equal(&lhs, &rhs) == matches!(op, ComparisonOperator::Equal)
         },
-        Expression::Atom(Atom::String(_s)) => todo!(),
-        Expression::Atom(Atom::End) => todo!(),
-        Expression::Path(lhs, rhs) => {
-            let intermediate = evaluate(lhs, data, environment)?;
-            if intermediate.is_array() {
-                let results: Vec<T> = intermediate
-                    .as_array()
-                    .unwrap() // todo Handle invalid array extraction
-                    .iter()
-                    .filter_map(|item| evaluate(rhs, item, environment).ok()) // Apply rhs to each item in the array
-                    .collect();
-
-                Ok(T::from_array(results)) // Combine results back into an array
+        ComparisonOperator::In => {
+            let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+                return Ok(Some(T::from_bool(false)));
+            };
+            sequence(rhs).iter().any(|item| deep_equal(&lhs, item))
+        },
+        ComparisonOperator::Less | ComparisonOperator::LessEqual
+            | ComparisonOperator::Greater | ComparisonOperator::GreaterEqual => {
+            let comparable = |value: &Option<T>| match value {
+                Some(value) => value.as_f64().is_some() || value.as_str().is_some(),
+                None => true,
+            };
+            if !comparable(&lhs) || !comparable(&rhs) {
+                return Err(Error::T2010);
+            }
+            let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+                return Ok(Some(T::from_bool(false)));
+            };
+            let ordering = if let (Some(lhs), Some(rhs)) = (lhs.as_f64(), rhs.as_f64()) {
+                lhs.partial_cmp(&rhs)
+            } else if let (Some(lhs), Some(rhs)) = (lhs.as_str(), rhs.as_str()) {
+                Some(lhs.cmp(rhs))
             } else {
-                evaluate(rhs, &intermediate, environment)
+                return Err(Error::T2009);
+            };
+            match op {
+                ComparisonOperator::Less => ordering.is_some_and(|o| o.is_lt()),
+                ComparisonOperator::LessEqual => ordering.is_some_and(|o| o.is_le()),
+                ComparisonOperator::Greater => ordering.is_some_and(|o| o.is_gt()),
+                _ => ordering.is_some_and(|o| o.is_ge()),
+            }
+        },
+    };
+    Ok(Some(T::from_bool(res)))
+}
+
+/// Resolves an index into a sequence of `length` items, negative indexes count from the end.
+fn index(index: f64, length: usize) -> Option<usize> {
+    let index = index.floor();
+    let index = if index < 0.0 { length as f64 + index } else { index };
+    if index >= 0.0 && index < length as f64 {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+/// Whether the result of a predicate selects the `i`th of `length` items.
+fn selects<T: JsonataData>(res: &T, i: usize, length: usize) -> bool {
+    // A number, or an array of numbers, selects items by index
+    let indexes: Option<Vec<f64>> = match res.as_array() {
+        Some(array) if !array.is_empty() => array.iter().map(|value| value.as_f64()).collect(),
+        Some(_) => None,
+        None => res.as_f64().map(|n| vec![n]),
+    };
+    match indexes {
+        Some(indexes) => indexes.into_iter().any(|n| index(n, length) == Some(i)),
+        None => boolean(res),
+    }
+}
+
+/// The items of a path step, each with the environment to evaluate the rest of the path in.
+/// The environments hold the variables bound by `@` and `#` and the parent of the item.
+fn evaluate_step<T: JsonataData + Clone + 'static>(step: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Vec<(T, Environment<T>)>> {
+    match step {
+        Expression::Focus(lhs, name) => {
+            let mut tuples = vec![];
+            for (item, scope) in evaluate_step(lhs, data, root, environment)? {
+                let scope = scope.extend();
+                scope.bind_value(name.clone(), Some(Value::Data(item)));
+                // The context stays where it was, the variable refers to the item
+                tuples.push((data.clone(), scope));
             }
+            Ok(tuples)
         },
-        Expression::BinaryNumeric(op, lhs, rhs) => evalute_numeric_binary(op, lhs, rhs, data, environment),
-        Expression::Unary(_op, _lhs) => {
-            todo!();
+        Expression::Index(lhs, name) => {
+            let mut tuples = vec![];
+            for (i, (item, scope)) in evaluate_step(lhs, data, root, environment)?.into_iter().enumerate() {
+                let scope = scope.extend();
+                scope.bind_value(name.clone(), Some(Value::Data(T::from_f64(i as f64))));
+                tuples.push((item, scope));
+            }
+            Ok(tuples)
         },
-        Expression::Variable(name, variable) => {
-            match variable {
-                jsonata_expression::Variable::Value => {
-                    match environment.lookup(name) {
-                        Some(Binding::Value(value)) => Ok(value.clone()),
-                        Some(Binding::Function(_)) => todo!("??"),
-                        None => todo!("** no match **"),
+        Expression::Predicate(lhs, predicate) => {
+            let tuples = evaluate_step(lhs, data, root, environment)?;
+            let length = tuples.len();
+            let mut selected = vec![];
+            for (i, (item, scope)) in tuples.into_iter().enumerate() {
+                if let Some(res) = evaluate(predicate, &item, root, &scope)? {
+                    if selects(&res, i, length) {
+                        selected.push((item, scope));
                     }
-                },
-                jsonata_expression::Variable::Function(args) => {
-                    match environment.lookup(name) {
-                        Some(Binding::Value(_value)) => todo!("Error: Attempted to invoke a non-function"),
-                        Some(Binding::Function(Function{implementation})) => {
-                            let args: Result<Vec<T>> = args
-                                .iter()
-                                .map(|item| evaluate(item, data, environment))
-                                .collect();
-                            implementation(args?)
-                        },
-                        None => todo!("** no match **"),
+                }
+            }
+            Ok(selected)
+        },
+        _ => {
+            let Some(value) = evaluate(step, data, root, environment)? else {
+                return Ok(vec![]);
+            };
+            let scope = step_environment(step, data, environment);
+            Ok(sequence(value).into_iter().map(|item| (item, scope.clone())).collect())
+        },
+    }
+}
+
+fn evaluate_order_by<T: JsonataData + Clone + 'static>(lhs: &Expression, terms: &[(Expression, SortOrder)], data: &T, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    let Some(input) = evaluate(lhs, data, root, environment)? else {
+        return Ok(None);
+    };
+    let scope = step_environment(lhs, data, environment);
+    // The sort keys are evaluated once for every item
+    let mut keyed = vec![];
+    for item in sequence(input) {
+        let mut keys = vec![];
+        for (expression, _) in terms {
+            let key = evaluate(expression, &item, root, &scope)?;
+            if key.as_ref().is_some_and(|key| key.as_f64().is_none() && key.as_str().is_none()) {
+                return Err(Error::T2008);
+            }
+            keys.push(key);
+        }
+        keyed.push((keys, item));
+    }
+    let mut error = None;
+    // `sort_by` is stable, so items with equal keys keep their order
+    keyed.sort_by(|(lhs, _), (rhs, _)| {
+        for ((lhs, rhs), (_, order)) in lhs.iter().zip(rhs).zip(terms) {
+            let ordering = match (lhs, rhs) {
+                // Undefined keys sort last, whatever the order
+                (None, None) => continue,
+                (None, Some(_)) => return Ordering::Greater,
+                (Some(_), None) => return Ordering::Less,
+                (Some(lhs), Some(rhs)) => {
+                    if let (Some(lhs), Some(rhs)) = (lhs.as_f64(), rhs.as_f64()) {
+                        lhs.partial_cmp(&rhs).unwrap_or(Ordering::Equal)
+                    } else if let (Some(lhs), Some(rhs)) = (lhs.as_str(), rhs.as_str()) {
+                        lhs.cmp(rhs)
+                    } else {
+                        error = Some(Error::T2007);
+                        Ordering::Equal
                     }
                 },
+            };
+            let ordering = match order {
+                SortOrder::Ascending => ordering,
+                SortOrder::Descending => ordering.reverse(),
+            };
+            if ordering.is_ne() {
+                return ordering;
             }
+        }
+        Ordering::Equal
+    });
+    if let Some(error) = error {
+        return Err(error);
+    }
+    Ok(collapse(keyed.into_iter().map(|(_, item)| item).collect()))
+}
+
+/// The largest number of items the range operator may allocate.
+const RANGE_LIMIT: f64 = 1e7;
+
+fn evaluate_range<T: JsonataData + Clone + 'static>(lhs: &Expression, rhs: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    let integer = |value: Option<T>, error: Error| match value {
+        Some(value) => match value.as_f64() {
+            Some(n) if n.fract() == 0.0 => Ok(Some(n)),
+            _ => Err(error),
         },
+        None => Ok(None),
+    };
+    let lhs = integer(evaluate(lhs, data, root, environment)?, Error::D2003)?;
+    let rhs = integer(evaluate(rhs, data, root, environment)?, Error::D2004)?;
+    let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+        return Ok(None);
+    };
+    if lhs > rhs {
+        return Ok(None);
+    }
+    if rhs - lhs + 1.0 > RANGE_LIMIT {
+        return Err(Error::D2014);
+    }
+    let items = (lhs as i64..=rhs as i64).map(|n| T::from_f64(n as f64)).collect();
+    Ok(Some(T::from_array(items)))
+}
+
+pub fn evaluate<T: JsonataData + Clone + 'static>(expr: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<T>> {
+    match expr {
+        Expression::Atom(Atom::Number(n)) => Ok(Some(T::from_f64(*n))),
+        Expression::Atom(Atom::Name(n)) => Ok(lookup(data, n)),
+        Expression::Atom(Atom::String(s)) => Ok(Some(T::from_string(s.clone()))),
+        Expression::Atom(Atom::Bool(b)) => Ok(Some(T::from_bool(*b))),
+        Expression::Atom(Atom::Null) => Ok(Some(T::null())),
+        Expression::Atom(Atom::End) => Ok(None),
+        Expression::Context => Ok(Some(data.clone())),
+        Expression::Root => Ok(Some(root.clone())),
+        Expression::Wildcard => Ok(wildcard(data)),
+        Expression::Parent(_) => Ok(environment.parent()),
+        // The parser only allows placeholders as arguments, which partial application binds
+        Expression::Placeholder => Err(Error::S0500),
+        Expression::Descendant => {
+            let mut results = vec![];
+            descendants(data, &mut results);
+            Ok(collapse(results))
+        },
+        Expression::Path(lhs, rhs) => {
+            let mut results = vec![];
+            for (item, scope) in evaluate_step(lhs, data, root, environment)? {
+                if let Some(result) = evaluate(rhs, &item, root, &scope)? {
+                    results.push(result);
+                }
+            }
+            // A single array from the last step is kept as is, otherwise the results are
+            // flattened unless the step constructs arrays itself
+            if results.len() == 1 {
+                Ok(results.pop())
+            } else if let Expression::ArrayConstructor(_) = **rhs {
+                Ok(collapse(results))
+            } else {
+                Ok(flatten(results))
+            }
+        },
+        Expression::ArrayConstructor(items) => {
+            let mut array = vec![];
+            for item in items {
+                match evaluate(item, data, root, environment)? {
+                    // Nested array constructors keep their structure
+                    Some(value) if matches!(item, Expression::ArrayConstructor(_)) => array.push(value),
+                    Some(value) => array.extend(sequence(value)),
+                    None => {},
+                }
+            }
+            Ok(Some(T::from_array(array)))
+        },
+        Expression::Range(lhs, rhs) => evaluate_range(lhs, rhs, data, root, environment),
+        Expression::ObjectConstructor(pairs) => evaluate_group(pairs, Some(data.clone()), root, environment),
+        Expression::GroupBy(lhs, pairs) => {
+            let input = evaluate(lhs, data, root, environment)?;
+            evaluate_group(pairs, input, root, &step_environment(lhs, data, environment))
+        },
+        Expression::OrderBy(lhs, terms) => evaluate_order_by(lhs, terms, data, root, environment),
+        Expression::Concatenate(lhs, rhs) => evaluate_concatenate(lhs, rhs, data, root, environment),
+        Expression::Predicate(..) | Expression::Focus(..) | Expression::Index(..) => {
+            let items = evaluate_step(expr, data, root, environment)?.into_iter().map(|(item, _)| item).collect();
+            Ok(collapse(items))
+        },
+        Expression::BinaryNumeric(op, lhs, rhs) => evalute_numeric_binary(op, lhs, rhs, data, root, environment),
+        Expression::BinaryBoolean(op, lhs, rhs) => evaluate_boolean_binary(op, lhs, rhs, data, root, environment),
+        Expression::Comparison(op, lhs, rhs) => evaluate_comparison(op, lhs, rhs, data, root, environment),
+        Expression::Unary(NumericUnaryOperator::Negate, rhs) => {
+            match evaluate(rhs, data, root, environment)? {
+                Some(value) => Ok(Some(T::from_f64(-value.as_f64().ok_or(Error::T2001)?))),
+                None => Ok(None),
+            }
+        },
+        Expression::Condition(..) | Expression::Default(..) | Expression::Coalesce(..) | Expression::Chain(..)
+            | Expression::Transform { .. }
+            | Expression::Variable(..) | Expression::Call(..) | Expression::Assign(..) | Expression::Block(..)
+            | Expression::Lambda { .. } | Expression::Atom(Atom::Regex { .. }) => {
+            Ok(evaluate_value(expr, data, root, environment)?.and_then(Value::data))
+        },
+    }
+}
+
+/// Calls a function value with the given arguments.
+pub(crate) fn apply<T>(function: Option<Value<T>>, args: Vec<Option<Value<T>>>) -> Result<Option<Value<T>>> {
+    match function {
+        Some(Value::Function(function)) => (function.implementation)(args),
+        _ => Err(Error::T1006),
+    }
+}
+
+/// The function a regular expression evaluates to: called with a string, it finds the first match
+/// and results in an object with the matched text, where it starts and ends and the captured groups.
+/// Unlike in jsonata-js the object has no `next` function, as data can't hold functions.
+fn matcher<T: JsonataData + Clone + 'static>(pattern: &str, flags: &str) -> Result<Value<T>> {
+    let regex = regex::RegexBuilder::new(pattern)
+        .case_insensitive(flags.contains('i'))
+        .multi_line(flags.contains('m'))
+        .build()
+        .map_err(|_| Error::S0500)?;
+    let implementation = move |args: Vec<Option<Value<T>>>| {
+        let Some(input) = args.into_iter().next().flatten().and_then(Value::data) else {
+            return Ok(None);
+        };
+        let Some((text, captures)) = input.as_str().and_then(|text| Some((text, regex.captures(text)?))) else {
+            return Ok(None);
+        };
+        let matched = captures.get(0).map(|matched| matched.range()).unwrap_or_default();
+        // Positions are counted in characters rather than bytes
+        let start = text[..matched.start].chars().count();
+        let end = start + text[matched.clone()].chars().count();
+        let groups = captures
+            .iter()
+            .skip(1)
+            .map(|group| group.map_or_else(T::null, |group| T::from_string(group.as_str().to_string())))
+            .collect();
+        Ok(Some(Value::Data(T::from_object(vec![
+            ("match".to_string(), T::from_string(text[matched].to_string())),
+            ("start".to_string(), T::from_f64(start as f64)),
+            ("end".to_string(), T::from_f64(end as f64)),
+            ("groups".to_string(), T::from_array(groups)),
+        ]))))
+    };
+    Ok(Value::Function(Rc::new(Callable {
+        arity: Some(1),
+        implementation: Box::new(implementation),
+    })))
+}
+
+/// A function that applies `first` and then `second` to the result.
+fn compose<T: 'static>(first: Rc<Callable<T>>, second: Rc<Callable<T>>) -> Value<T> {
+    let arity = first.arity;
+    let implementation = move |args| {
+        let intermediate = (first.implementation)(args)?;
+        (second.implementation)(vec![intermediate])
+    };
+    Value::Function(Rc::new(Callable {
+        arity,
+        implementation: Box::new(implementation),
+    }))
+}
+
+/// Calls `function` with the `leading` arguments followed by `args`.
+fn evaluate_call<T: JsonataData + Clone + 'static>(function: Option<Value<T>>, args: &[Expression], mut leading: Vec<Option<Value<T>>>, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<Value<T>>> {
+    for arg in args {
+        leading.push(evaluate_value(arg, data, root, environment)?);
     }
+    apply(function, leading)
 }
 
+fn is_partial(args: &[Expression]) -> bool {
+    args.iter().any(|arg| matches!(arg, Expression::Placeholder))
+}
+
+/// A function that calls the function `callee` evaluates to with the given arguments, where
+/// the placeholders are filled in by the arguments of the new function.
+fn evaluate_partial<T: JsonataData + Clone + 'static>(callee: &Expression, args: &[Expression], data: &T, root: &T, environment: &Environment<T>) -> Result<Option<Value<T>>> {
+    let function = match evaluate_value(callee, data, root, environment)? {
+        Some(Value::Function(function)) => function,
+        // A name is most likely a function name missing its `$`
+        _ if matches!(callee, Expression::Atom(Atom::Name(_))) => return Err(Error::T1007),
+        _ => return Err(Error::T1008),
+    };
+    let mut bound = vec![];
+    for arg in args {
+        match arg {
+            Expression::Placeholder => bound.push(None),
+            arg => bound.push(Some(evaluate_value(arg, data, root, environment)?)),
+        }
+    }
+    let arity = bound.iter().filter(|arg| arg.is_none()).count();
+    let implementation = move |args: Vec<Option<Value<T>>>| {
+        let mut args = args.into_iter();
+        let args = bound
+            .iter()
+            .map(|arg| match arg {
+                Some(arg) => arg.clone(),
+                None => args.next().flatten(),
+            })
+            .collect();
+        (function.implementation)(args)
+    };
+    Ok(Some(Value::Function(Rc::new(Callable {
+        arity: Some(arity),
+        implementation: Box::new(implementation),
+    }))))
+}
+
+/// Evaluates an expression that may result in a function as well as in data.
+fn evaluate_value<T: JsonataData + Clone + 'static>(expr: &Expression, data: &T, root: &T, environment: &Environment<T>) -> Result<Option<Value<T>>> {
+    match expr {
+        Expression::Condition(condition, then, otherwise) => {
+            let condition = evaluate(condition, data, root, environment)?;
+            if condition.is_some_and(|value| boolean(&value)) {
+                evaluate_value(then, data, root, environment)
+            } else if let Some(otherwise) = otherwise {
+                evaluate_value(otherwise, data, root, environment)
+            } else {
+                Ok(None)
+            }
+        },
+        Expression::Default(lhs, rhs) => {
+            match evaluate_value(lhs, data, root, environment)? {
+                // Functions are falsy like in `$boolean`
+                Some(Value::Data(value)) if boolean(&value) => Ok(Some(Value::Data(value))),
+                _ => evaluate_value(rhs, data, root, environment),
+            }
+        },
+        Expression::Coalesce(lhs, rhs) => {
+            match evaluate_value(lhs, data, root, environment)? {
+                Some(value) => Ok(Some(value)),
+                None => evaluate_value(rhs, data, root, environment),
+            }
+        },
+        Expression::Atom(Atom::Regex { pattern, flags }) => matcher(pattern, flags).map(Some),
+        Expression::Variable(name, jsonata_expression::Variable::Value) => Ok(environment.lookup(name)),
+        Expression::Variable(name, jsonata_expression::Variable::Function(args)) if is_partial(args) => {
+            let callee = Expression::Variable(name.clone(), jsonata_expression::Variable::Value);
+            evaluate_partial(&callee, args, data, root, environment)
+        },
+        Expression::Variable(name, jsonata_expression::Variable::Function(args)) => {
+            evaluate_call(environment.lookup(name), args, vec![], data, root, environment)
+        },
+        Expression::Call(callee, args) if is_partial(args) => evaluate_partial(callee, args, data, root, environment),
+        Expression::Call(callee, args) => {
+            let function = evaluate_value(callee, data, root, environment)?;
+            evaluate_call(function, args, vec![], data, root, environment)
+        },
+        Expression::Chain(lhs, rhs) => {
+            let lhs = evaluate_value(lhs, data, root, environment)?;
+            // A call on the right side gets the left side as its first argument
+            match &**rhs {
+                Expression::Variable(name, jsonata_expression::Variable::Function(args)) if !is_partial(args) => {
+                    return evaluate_call(environment.lookup(name), args, vec![lhs], data, root, environment);
+                },
+                Expression::Call(callee, args) if !is_partial(args) => {
+                    let function = evaluate_value(callee, data, root, environment)?;
+                    return evaluate_call(function, args, vec![lhs], data, root, environment);
+                },
+                _ => {},
+            }
+            let Some(Value::Function(function)) = evaluate_value(rhs, data, root, environment)? else {
+                return Err(Error::T2006);
+            };
+            match lhs {
+                Some(Value::Function(first)) => Ok(Some(compose(first, function))),
+                lhs => (function.implementation)(vec![lhs]),
+            }
+        },
+        Expression::Assign(name, rhs) => {
+            let value = evaluate_value(rhs, data, root, environment)?;
+            environment.bind_value(name.clone(), value.clone());
+            Ok(value)
+        },
+        Expression::Block(expressions) => {
+            let scope = environment.extend();
+            let mut result = None;
+            for expression in expressions {
+                result = evaluate_value(expression, data, root, &scope)?;
+            }
+            Ok(result)
+        },
+        Expression::Lambda { params, body, .. } => {
+            // The body is evaluated against the context and in the environment of the definition
+            let arity = params.len();
+            let (params, body) = (params.clone(), Rc::clone(body));
+            let (input, root, environment) = (data.clone(), root.clone(), environment.capture());
+            let implementation = move |args: Vec<Option<Value<T>>>| {
+                let scope = environment.extend();
+                let mut args = args.into_iter();
+                for param in &params {
+                    scope.bind_value(param.clone(), args.next().flatten());
+                }
+                evaluate_value(&body, &input, &root, &scope)
+            };
+            Ok(Some(Value::Function(Rc::new(Callable {
+                arity: Some(arity),
+                implementation: Box::new(implementation),
+            }))))
+        },
+        Expression::Transform { pattern, update, delete } => {
+            let (pattern, update, delete) = (Rc::clone(pattern), Rc::clone(update), delete.clone());
+            let (root, environment) = (root.clone(), environment.capture());
+            let implementation = move |args: Vec<Option<Value<T>>>| {
+                let Some(input) = args.into_iter().next().flatten().and_then(Value::data) else {
+                    return Ok(None);
+                };
+                let result = T::transform(&pattern, &update, delete.as_deref(), &input, &root, &environment)?;
+                Ok(Some(Value::Data(result)))
+            };
+            Ok(Some(Value::Function(Rc::new(Callable {
+                arity: Some(1),
+                implementation: Box::new(implementation),
+            }))))
+        },
+        _ => Ok(evaluate(expr, data, root, environment)?.map(Value::Data)),
+    }
+}
@@ -1,15 +1,19 @@
-pub use jsonata_error::{Result, Error};
+pub use jsonata_error::{Result, Error, Position};
 use jsonata_expression::Expression;
 use jsonata_parser::Parser;
 
 mod evaluate;
-use evaluate::evaluate;
+use evaluate::{apply, collapse, evaluate, sequence};
 
 mod environment;
-use environment::{Binding, Environment, Function};
+use environment::{Binding, Callable, Captures, Environment, Function, Value};
+
+use std::rc::Rc;
 
 mod data;
-use data::JsonataData;
+use data::{JsonataData, Segment};
+
+mod transform;
 
 pub fn jsonata<T: JsonataData + Clone + 'static> (expr: &str) -> Result<Jsonata<T>> {
     let parser = Parser::new(expr);
@@ -19,15 +23,42 @@ pub fn jsonata<T: JsonataData + Clone + 'static> (expr: &str) -> Result<Jsonata<
 }
 
 fn sum<T: JsonataData + Clone>(args: Vec<T>) -> Result<T> {
-    let arg = args.get(0).expect("Should have one arg, todo: handle error correctly");
-    let sum: f64 = arg
-        .as_array().expect("should be an array")
-        .iter()
-        .filter_map(|v| v.as_f64())
-        .sum();
+    let arg = args.first().expect("Should have one arg, todo: handle error correctly");
+    let sum: f64 = match arg.as_array() {
+        Some(array) => array.iter().filter_map(|v| v.as_f64()).sum(),
+        None => arg.as_f64().unwrap_or_default(),
+    };
     Ok(T::from_f64(sum))
 }
 
+/// `$map(array, function)`, the function is called with as many of the item, its index and
+/// the array as it has parameters.
+fn map<T: JsonataData + Clone + 'static>(args: Vec<Option<Value<T>>>) -> Result<Option<Value<T>>> {
+    let mut args = args.into_iter();
+    let Some(array) = args.next().flatten().and_then(Value::data) else {
+        return Ok(None);
+    };
+    let function = args.next().flatten();
+    let arity = match &function {
+        Some(Value::Function(function)) => function.arity.unwrap_or(1),
+        _ => return Err(crate::Error::T1006),
+    };
+    let items = sequence(array);
+    let mut results = vec![];
+    for (index, item) in items.iter().enumerate() {
+        let args = [
+            Value::Data(item.clone()),
+            Value::Data(T::from_f64(index as f64)),
+            Value::Data(T::from_array(items.clone())),
+        ];
+        let args = args.into_iter().take(arity).map(Some).collect();
+        if let Some(result) = apply(function.clone(), args)?.and_then(Value::data) {
+            results.push(result);
+        }
+    }
+    Ok(collapse(results).map(Value::Data))
+}
+
 pub struct Jsonata<T> {
     ast: Expression,
     environment: Environment<T>,
@@ -36,8 +67,12 @@ pub struct Jsonata<T> {
 impl<T: JsonataData + Clone + 'static> Jsonata<T> {
 
     pub fn new (ast: Expression) -> Self {
-        let mut environment = Environment::new();
+        let environment = Environment::new();
         environment.bind("sum".into(), Binding::Function(Function{implementation: Box::new(sum)}));
+        environment.bind_value("map".into(), Some(Value::Function(Rc::new(Callable {
+            arity: Some(2),
+            implementation: Box::new(map),
+        }))));
         Jsonata {
             ast,
             environment,
@@ -48,8 +83,12 @@ impl<T: JsonataData + Clone + 'static> Jsonata<T> {
         self.environment.bind(name, binding);
     }
 
+    /// Evaluates the expression against `data`, an undefined result is returned as null.
     pub fn evaluate(&self, data: &T) -> Result<T> {
-        evaluate(&self.ast, &data, &self.environment)
+        // Assignments outside of a block bind in a scope of their own, not in the bindings of `self`
+        let captures = Captures::new();
+        let scope = self.environment.evaluation(&captures);
+        Ok(evaluate(&self.ast, data, data, &scope)?.unwrap_or_else(T::null))
     }
 }
 
@@ -72,7 +111,7 @@ impl JsonataData for serde_json::Value {
 
 
     fn is_array(&self) -> bool {
-        serde_json::Value::is_array(&self)
+        serde_json::Value::is_array(self)
     }
 
     fn as_array(&self) -> Option<Vec<Self>>
@@ -83,6 +122,66 @@ impl JsonataData for serde_json::Value {
     fn from_array(array: Vec<Self>) -> Self {
         serde_json::Value::Array(array)
     }
+
+    fn as_object(&self) -> Option<Vec<(String, Self)>> {
+        self.as_object()
+            .map(|object| object.iter().map(|(key, value)| (key.clone(), value.clone())).collect())
+    }
+
+    fn from_object(entries: Vec<(String, Self)>) -> Self {
+        serde_json::Value::Object(entries.into_iter().collect())
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        serde_json::Value::as_bool(self)
+    }
+
+    fn from_bool(value: bool) -> Self {
+        serde_json::Value::Bool(value)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        serde_json::Value::as_str(self)
+    }
+
+    fn from_string(value: String) -> Self {
+        serde_json::Value::String(value)
+    }
+
+    fn is_null(&self) -> bool {
+        serde_json::Value::is_null(self)
+    }
+
+    fn null() -> Self {
+        serde_json::Value::Null
+    }
+
+    fn deep_clone(&self) -> Self {
+        self.clone()
+    }
+
+    fn modify(&mut self, location: &[Segment], f: &mut dyn FnMut(&mut Self) -> Result<()>) -> Result<()> {
+        let target = location.iter().try_fold(self, |value, segment| match segment {
+            Segment::Field(field) => value.get_mut(field.as_str()),
+            Segment::Index(index) => value.get_mut(*index),
+        });
+        match target {
+            Some(target) => f(target),
+            None => Ok(()),
+        }
+    }
+
+    fn merge(&mut self, entries: Vec<(String, Self)>) {
+        if let Some(object) = self.as_object_mut() {
+            object.extend(entries);
+        }
+    }
+
+    fn remove_field(&mut self, field: &str) {
+        if let Some(object) = self.as_object_mut() {
+            object.remove(field);
+        }
+    }
 }
 
 
@@ -108,7 +207,7 @@ mod tests {
     #[test]
     fn test_jsonata_function_bindings_with_args () -> Result<()> {
         let func = |args: Vec<serde_json::Value>| -> Result<serde_json::Value> {
-            let arg = args.get(0).expect("Should have one arg");
+            let arg = args.first().expect("Should have one arg");
             Ok(arg.clone())
         };
 
@@ -143,6 +242,98 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_jsonata_decimal_literal () -> Result<()> {
+        let expression = jsonata("x * 1.5 + 2.5e-1")?;
+        let result = expression.evaluate(&serde_json::json!({"x": 4.0}))?;
+        assert_eq!(result, serde_json::json!(6.25));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_quoted_name () -> Result<()> {
+        let data = serde_json::json!({"order-id": {"Unit Price": 2.5}});
+        let expression = jsonata("`order-id`.`Unit Price` * 2")?;
+        let result = expression.evaluate(&data)?;
+        assert_eq!(result, serde_json::json!(5.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_keyword_literals () -> Result<()> {
+        let data = serde_json::json!({});
+        assert_eq!(jsonata("true")?.evaluate(&data)?, serde_json::json!(true));
+        assert_eq!(jsonata("false")?.evaluate(&data)?, serde_json::json!(false));
+        assert_eq!(jsonata("null")?.evaluate(&data)?, serde_json::json!(null));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_boolean_operators () -> Result<()> {
+        let data = serde_json::json!({"paid": true, "qty": 0, "name": "x", "items": [0, 1]});
+        assert_eq!(jsonata("paid and qty")?.evaluate(&data)?, serde_json::json!(false));
+        assert_eq!(jsonata("paid and name")?.evaluate(&data)?, serde_json::json!(true));
+        assert_eq!(jsonata("qty or null")?.evaluate(&data)?, serde_json::json!(false));
+        assert_eq!(jsonata("qty or items")?.evaluate(&data)?, serde_json::json!(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_in_operator () -> Result<()> {
+        let data = serde_json::json!({"qty": 1, "items": [0, 1], "paid": true});
+        assert_eq!(jsonata("qty in items")?.evaluate(&data)?, serde_json::json!(true));
+        assert_eq!(jsonata("qty + 1 in items")?.evaluate(&data)?, serde_json::json!(false));
+        assert_eq!(jsonata("paid in paid and 0 in items")?.evaluate(&data)?, serde_json::json!(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_underscore_names () -> Result<()> {
+        let mut expression = jsonata("unit_price * $tax_rate")?;
+        expression.bind("tax_rate".into(), Binding::Value(serde_json::json!(2.0)));
+        let result = expression.evaluate(&serde_json::json!({"unit_price": 4.0}))?;
+        assert_eq!(result, serde_json::json!(8.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_context_and_root () -> Result<()> {
+        let data = serde_json::json!({
+            "rate": 2,
+            "order": {"qty": 3}
+        });
+        assert_eq!(jsonata("$")?.evaluate(&data)?, data);
+        assert_eq!(jsonata("order.$.qty")?.evaluate(&data)?, serde_json::json!(3));
+        assert_eq!(jsonata("order.($.qty * $$.rate)")?.evaluate(&data)?, serde_json::json!(6.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_regex_literal () -> Result<()> {
+        let data = serde_json::json!({"n": 4});
+        assert_eq!(jsonata("n / 2")?.evaluate(&data)?, serde_json::json!(2.0));
+        assert_eq!(jsonata("/a+/ * n")?.evaluate(&data)?, serde_json::json!(null));
+        assert_eq!(jsonata::<serde_json::Value>("/a(?=b)/")?.evaluate(&data).unwrap_err(), crate::Error::S0500);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_regex_matcher() -> Result<()> {
+        let data = serde_json::json!({"s": "éxAbY"});
+        let expression = jsonata("/a(b)(c)?/i(s)")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "match": "Ab", "start": 2.0, "end": 4.0, "groups": ["b", null]
+        }));
+
+        let expression = jsonata("($m := /^x/m; $m(\"a\\nxb\").start)")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(2.0));
+
+        // Functions have no representation as data, nor does a failed match
+        assert_eq!(jsonata("/a+/i")?.evaluate(&data)?, serde_json::json!(null));
+        assert_eq!(jsonata("/z/(s)")?.evaluate(&data)?, serde_json::json!(null));
+        Ok(())
+    }
+
     #[test]
     fn test_jsonata_path_array() -> Result<()> {
         let data = serde_json::json!({
@@ -158,6 +349,629 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_jsonata_missing_path() -> Result<()> {
+        let data = serde_json::json!({"x": {"a": 1}});
+        assert_eq!(jsonata("x.b")?.evaluate(&data)?, serde_json::json!(null));
+        assert_eq!(jsonata("y.a")?.evaluate(&data)?, serde_json::json!(null));
+        assert_eq!(jsonata("x.b + 1")?.evaluate(&data)?, serde_json::json!(null));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_comparison() -> Result<()> {
+        let data = serde_json::json!({"qty": 4, "name": "b", "tags": ["x", "y"]});
+        assert_eq!(jsonata("qty = 4")?.evaluate(&data)?, serde_json::json!(true));
+        assert_eq!(jsonata("qty != 4")?.evaluate(&data)?, serde_json::json!(false));
+        assert_eq!(jsonata("qty >= 4 and qty < 4.5")?.evaluate(&data)?, serde_json::json!(true));
+        assert_eq!(jsonata("name > \"a\"")?.evaluate(&data)?, serde_json::json!(true));
+        assert_eq!(jsonata("\"y\" in tags")?.evaluate(&data)?, serde_json::json!(true));
+        assert_eq!(jsonata("missing = null")?.evaluate(&data)?, serde_json::json!(false));
+        assert_eq!(jsonata("qty < name").map(|e| e.evaluate(&data)), Ok(Err(crate::Error::T2009)));
+        assert_eq!(jsonata("tags < 1").map(|e| e.evaluate(&data)), Ok(Err(crate::Error::T2010)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_predicate_index() -> Result<()> {
+        let data = serde_json::json!({
+            "Phone": [
+                {"type": "home", "number": "0203 544 1234"},
+                {"type": "office", "number": "01962 001234"},
+                {"type": "mobile", "number": "077 7700 1234"}
+            ]
+        });
+        assert_eq!(jsonata("Phone[0].number")?.evaluate(&data)?, serde_json::json!("0203 544 1234"));
+        assert_eq!(jsonata("Phone[-1].type")?.evaluate(&data)?, serde_json::json!("mobile"));
+        assert_eq!(jsonata("Phone[1.7].type")?.evaluate(&data)?, serde_json::json!("office"));
+        assert_eq!(jsonata("Phone[3]")?.evaluate(&data)?, serde_json::json!(null));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_predicate_filter() -> Result<()> {
+        let data = serde_json::json!({
+            "Order": [
+                {"id": 1, "Price": 5, "Product": [{"sku": "a"}, {"sku": "b"}]},
+                {"id": 2, "Price": 25, "Product": [{"sku": "c"}]},
+                {"id": 3, "Price": 15, "Product": [{"sku": "d"}, {"sku": "e"}]}
+            ]
+        });
+        assert_eq!(jsonata("Order[Price > 10].id")?.evaluate(&data)?, serde_json::json!([2, 3]));
+        assert_eq!(jsonata("Order[$.Price > 20].id")?.evaluate(&data)?, serde_json::json!(2));
+        assert_eq!(jsonata("Order[Price > 100]")?.evaluate(&data)?, serde_json::json!(null));
+        // Predicates on a step apply to each input item of that step
+        assert_eq!(jsonata("Order.Product[0].sku")?.evaluate(&data)?, serde_json::json!(["a", "c", "d"]));
+        assert_eq!(jsonata("Order[Price > 10][-1].id")?.evaluate(&data)?, serde_json::json!(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_array_constructor() -> Result<()> {
+        let data = serde_json::json!({
+            "Address": [{"City": "Winchester"}, {"City": "London"}],
+            "count": 3
+        });
+        assert_eq!(jsonata("[1, 2, 3]")?.evaluate(&data)?, serde_json::json!([1.0, 2.0, 3.0]));
+        assert_eq!(jsonata("[]")?.evaluate(&data)?, serde_json::json!([]));
+        assert_eq!(jsonata("[count]")?.evaluate(&data)?, serde_json::json!([3]));
+        assert_eq!(jsonata("[Address.City, \"Unknown\", missing]")?.evaluate(&data)?, serde_json::json!(["Winchester", "London", "Unknown"]));
+        assert_eq!(jsonata("[[1, 2], [3]]")?.evaluate(&data)?, serde_json::json!([[1.0, 2.0], [3.0]]));
+        assert_eq!(jsonata("Address.[City]")?.evaluate(&data)?, serde_json::json!([["Winchester"], ["London"]]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_range() -> Result<()> {
+        let mut expression = jsonata("[1..$count]")?;
+        expression.bind("count".into(), Binding::Value(serde_json::json!(3)));
+        assert_eq!(expression.evaluate(&serde_json::json!({}))?, serde_json::json!([1.0, 2.0, 3.0]));
+
+        let data = serde_json::json!({"a": 1.5, "s": "x"});
+        assert_eq!(jsonata("[0, 3..4, 2..1]")?.evaluate(&data)?, serde_json::json!([0.0, 3.0, 4.0]));
+        assert_eq!(jsonata("[1..3][-1]")?.evaluate(&data)?, serde_json::json!(3.0));
+        assert_eq!(jsonata("[a..3]").map(|e| e.evaluate(&data)), Ok(Err(crate::Error::D2003)));
+        assert_eq!(jsonata("[1..s]").map(|e| e.evaluate(&data)), Ok(Err(crate::Error::D2004)));
+        assert_eq!(jsonata("[1..20000000]").map(|e| e.evaluate(&data)), Ok(Err(crate::Error::D2014)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_object_constructor() -> Result<()> {
+        let data = serde_json::json!({
+            "FirstName": "Fred",
+            "Surname": "Smith",
+            "Price": [2, 3],
+            "Type": "customer",
+            "Count": 2
+        });
+        let expression = jsonata("{ \"name\": FirstName & \" \" & Surname, \"total\": $sum(Price), Type: Count, \"none\": Missing }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "name": "Fred Smith",
+            "total": 5.0,
+            "customer": 2
+        }));
+        assert_eq!(jsonata("{}")?.evaluate(&data)?, serde_json::json!({}));
+        assert_eq!(jsonata("{Count: 1}").map(|e| e.evaluate(&data)), Ok(Err(crate::Error::T1003)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_group_by() -> Result<()> {
+        let data = serde_json::json!({
+            "Account": {
+                "Order": [
+                    {"Product": [{"SKU": "a", "Price": 2}, {"SKU": "b", "Price": 5}]},
+                    {"Product": [{"SKU": "a", "Price": 3}]}
+                ]
+            }
+        });
+        let expression = jsonata("Account.Order.Product{ SKU: $sum(Price) }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({"a": 5.0, "b": 5.0}));
+
+        let expression = jsonata("Account.Order.Product{ SKU: Price }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({"a": [2, 3], "b": 5}));
+
+        let expression = jsonata("Account.Order.Product.{ SKU: Price }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([{"a": 2}, {"b": 5}, {"a": 3}]));
+
+        let expression = jsonata("Account.Missing{ \"count\": 0 }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({"count": 0.0}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_group_by_duplicate_keys() -> Result<()> {
+        let data = serde_json::json!({"Product": [{"SKU": "a", "Name": "b"}, {"SKU": "b", "Name": "c"}]});
+        let expression = jsonata("Product{ SKU: 1, Name: 2 }")?;
+        assert_eq!(expression.evaluate(&data), Err(crate::Error::D1009));
+
+        let expression = jsonata("{ \"a\": 1, \"a\": 2 }")?;
+        assert_eq!(expression.evaluate(&data), Err(crate::Error::D1009));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_condition() -> Result<()> {
+        let data = serde_json::json!({"qty": 5, "empty": ""});
+        assert_eq!(jsonata("qty > 3 ? \"many\" : \"few\"")?.evaluate(&data)?, serde_json::json!("many"));
+        assert_eq!(jsonata("qty > 9 ? \"many\" : \"few\"")?.evaluate(&data)?, serde_json::json!("few"));
+        assert_eq!(jsonata("missing ? 1 : 2")?.evaluate(&data)?, serde_json::json!(2.0));
+        assert_eq!(jsonata("[qty > 9 ? 1, 2]")?.evaluate(&data)?, serde_json::json!([2.0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_default_and_coalesce() -> Result<()> {
+        let data = serde_json::json!({"qty": 0, "empty": "", "none": null, "name": "x"});
+        assert_eq!(jsonata("name ?: \"unknown\"")?.evaluate(&data)?, serde_json::json!("x"));
+        assert_eq!(jsonata("empty ?: \"unknown\"")?.evaluate(&data)?, serde_json::json!("unknown"));
+        assert_eq!(jsonata("qty ?: missing ?: 7")?.evaluate(&data)?, serde_json::json!(7.0));
+        assert_eq!(jsonata("qty ?? 7")?.evaluate(&data)?, serde_json::json!(0));
+        assert_eq!(jsonata("none ?? 7")?.evaluate(&data)?, serde_json::json!(null));
+        assert_eq!(jsonata("missing ?? 7")?.evaluate(&data)?, serde_json::json!(7.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_order_by() -> Result<()> {
+        let data = serde_json::json!({
+            "Account": [
+                {"Order": [{"Name": "b", "Price": 10}, {"Name": "a", "Price": 30}]},
+                {"Order": [{"Name": "c", "Price": 10}, {"Name": "d"}]},
+            ]
+        });
+        let expression = jsonata("Account.Order^(Price).Name")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["b", "c", "a", "d"]));
+
+        let expression = jsonata("Account.Order^(>Price, >Name).Name")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["a", "c", "b", "d"]));
+
+        let expression = jsonata("Account.Order^(<Price)[0].Name")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!("b"));
+
+        let expression = jsonata("[3, 1, 2]^($)")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([1.0, 2.0, 3.0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_order_by_errors() -> Result<()> {
+        let data = serde_json::json!({"items": [{"k": 1}, {"k": "a"}, {"k": true}]});
+        assert_eq!(jsonata("items[[0, 1]]^(k)")?.evaluate(&data), Err(crate::Error::T2007));
+        assert_eq!(jsonata("items^(k)")?.evaluate(&data), Err(crate::Error::T2008));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_wildcard() -> Result<()> {
+        let data = serde_json::json!({
+            "Address": {"City": "Oslo", "Phone": ["1", ["2"]], "Street": "Main"},
+            "Items": [{"Price": 2}, {"Price": 3}]
+        });
+        let expression = jsonata("Address.*")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["Oslo", "1", "2", "Main"]));
+
+        let expression = jsonata("Items[0].* * 2")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(4.0));
+
+        let expression = jsonata("*.City")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!("Oslo"));
+
+        let expression = jsonata("Address.Street.*")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(null));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_descendant() -> Result<()> {
+        let data = serde_json::json!({
+            "Home": {"Address": {"Postcode": "0150"}},
+            "Work": [{"Address": {"Postcode": "0160", "Floor": {"Postcode": "0161"}}}]
+        });
+        let expression = jsonata("**.Postcode")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["0150", "0160", "0161"]));
+
+        let expression = jsonata("Work.**.Floor.Postcode")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!("0161"));
+
+        let expression = jsonata("Home.**")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([
+            {"Address": {"Postcode": "0150"}},
+            {"Postcode": "0150"},
+            "0150"
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_parent() -> Result<()> {
+        let data = serde_json::json!({
+            "Account": {
+                "Name": "Firefly",
+                "Order": [
+                    {"OrderID": "order1", "Product": [{"SKU": "a"}, {"SKU": "b"}]},
+                    {"OrderID": "order2", "Product": {"SKU": "c"}}
+                ]
+            }
+        });
+        let expression = jsonata("Account.Order.Product.{ \"order\": %.OrderID, \"sku\": SKU }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([
+            {"order": "order1", "sku": "a"},
+            {"order": "order1", "sku": "b"},
+            {"order": "order2", "sku": "c"}
+        ]));
+
+        let expression = jsonata("Account.Order.Product[SKU = \"c\"].%.%.Name")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!("Firefly"));
+
+        let expression = jsonata("Account.Order.Product[%.OrderID = \"order1\"].SKU")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["a", "b"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_modulo() -> Result<()> {
+        assert_eq!(jsonata("7 % 3")?.evaluate(&serde_json::json!({}))?, serde_json::json!(1.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_index_binding() -> Result<()> {
+        let data = serde_json::json!({"Order": [{"Product": "a"}, {"Product": "b"}, {"Product": "c"}]});
+        let expression = jsonata("Order#$i.{ \"line\": $i + 1, \"product\": Product }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([
+            {"line": 1.0, "product": "a"},
+            {"line": 2.0, "product": "b"},
+            {"line": 3.0, "product": "c"}
+        ]));
+
+        let expression = jsonata("Order#$i[$i > 0].Product")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["b", "c"]));
+
+        let expression = jsonata("Order[Product != \"a\"]#$i.($i & Product)")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["0b", "1c"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_focus_binding() -> Result<()> {
+        let data = serde_json::json!({
+            "loans": [{"isbn": "1", "customer": "ann"}, {"isbn": "2", "customer": "bob"}],
+            "books": [{"isbn": "2", "title": "Dune"}, {"isbn": "1", "title": "Emma"}]
+        });
+        let expression = jsonata("loans@$l.books@$b[$l.isbn = $b.isbn].{ \"customer\": $l.customer, \"title\": $b.title }")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([
+            {"customer": "ann", "title": "Emma"},
+            {"customer": "bob", "title": "Dune"}
+        ]));
+
+        // The context stays on the input, so the last step is evaluated against it
+        let expression = jsonata("books@$b.loans[isbn = $b.isbn].customer")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["bob", "ann"]));
+
+        // The parent of a focused step is the context the step navigated from
+        let expression = jsonata("loans@$l.%.books[isbn = $l.isbn].title")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["Emma", "Dune"]));
+
+        // Like in jsonata-js the parent is the input once per loan
+        let expression = jsonata("loans@$l.%")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([data, data]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_transform() -> Result<()> {
+        let data = serde_json::json!({
+            "Account": {
+                "Order": [
+                    {"id": 1, "draft": true, "Price": 10},
+                    {"id": 2, "draft": false, "Price": 30}
+                ]
+            }
+        });
+        let expression = jsonata("$ ~> | Account.Order | {\"status\": \"shipped\"}, [\"draft\"] |")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "Account": {
+                "Order": [
+                    {"id": 1, "Price": 10, "status": "shipped"},
+                    {"id": 2, "Price": 30, "status": "shipped"}
+                ]
+            }
+        }));
+
+        let expression = jsonata("(Account ~> |Order[Price > 20]|{\"Price\": Price * 2}, \"draft\"|).Order.Price")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([10, 60.0]));
+
+        let expression = jsonata("$ ~> |**[id = 1]|{\"first\": true}| ~> |Account.Order[1]|{}, \"id\"|")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "Account": {
+                "Order": [
+                    {"id": 1, "draft": true, "Price": 10, "first": true},
+                    {"draft": false, "Price": 30}
+                ]
+            }
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_transform_errors() -> Result<()> {
+        let data = serde_json::json!({"a": {"b": 1}});
+        assert_eq!(jsonata("$ ~> |a|5|")?.evaluate(&data), Err(crate::Error::T2011));
+        assert_eq!(jsonata("$ ~> |a|{}, [\"b\", 1]|")?.evaluate(&data), Err(crate::Error::T2012));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_transform_step_bindings() -> Result<()> {
+        let data = serde_json::json!({
+            "Order": [
+                {"id": 1, "items": [{"qty": 1}]},
+                {"id": 2, "items": [{"qty": 3}, {"qty": 5}]}
+            ]
+        });
+        let expression = jsonata("$ ~> |Order#$i[$i = 1]|{\"second\": true}|")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "Order": [
+                {"id": 1, "items": [{"qty": 1}]},
+                {"id": 2, "items": [{"qty": 3}, {"qty": 5}], "second": true}
+            ]
+        }));
+
+        let expression = jsonata("$ ~> |Order^(>id)[0]|{\"last\": true}|")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "Order": [
+                {"id": 1, "items": [{"qty": 1}]},
+                {"id": 2, "items": [{"qty": 3}, {"qty": 5}], "last": true}
+            ]
+        }));
+
+        let data = serde_json::json!({
+            "orders": [{"pid": "2"}],
+            "products": [{"id": "1"}, {"id": "2"}]
+        });
+        let expression = jsonata("$ ~> |orders@$o.products[id = $o.pid]|{\"ordered\": true}|")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "orders": [{"pid": "2"}],
+            "products": [{"id": "1"}, {"id": "2", "ordered": true}]
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_transform_parent() -> Result<()> {
+        let data = serde_json::json!({
+            "Order": [
+                {"id": 1, "items": [{"qty": 1}]},
+                {"id": 2, "items": [{"qty": 3}, {"qty": 5}]}
+            ]
+        });
+        let expression = jsonata("$ ~> |Order.items[qty > 1].%|{\"bulk\": true}|")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "Order": [
+                {"id": 1, "items": [{"qty": 1}]},
+                {"id": 2, "items": [{"qty": 3}, {"qty": 5}], "bulk": true}
+            ]
+        }));
+
+        let expression = jsonata("$ ~> |Order.items[%.id = 1]|{\"first\": true}|")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!({
+            "Order": [
+                {"id": 1, "items": [{"qty": 1, "first": true}]},
+                {"id": 2, "items": [{"qty": 3}, {"qty": 5}]}
+            ]
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_transform_patterns() -> Result<()> {
+        let data = serde_json::json!({"a": {"b": 1}});
+        let changed = serde_json::json!({"a": {"b": 1, "c": "x"}});
+        assert_eq!(jsonata("$ ~> |(a)|{\"c\": \"x\"}|")?.evaluate(&data)?, changed);
+        assert_eq!(jsonata("$ ~> |a.[$]|{\"c\": \"x\"}|")?.evaluate(&data)?, changed);
+        assert_eq!(jsonata("$ ~> |($f := function($v) { $v.a }; $f($))|{\"c\": \"x\"}|")?.evaluate(&data)?, changed);
+
+        // Like in jsonata-js only what the pattern finds in the input is changed
+        assert_eq!(jsonata("$ ~> |$$.a|{\"c\": \"x\"}|")?.evaluate(&data)?, data);
+        assert_eq!(jsonata("($x := a; $ ~> |$x|{\"c\": \"x\"}|)")?.evaluate(&data)?, data);
+        assert_eq!(jsonata("$ ~> |a ~> |$|{\"d\": 3}||{\"c\": \"x\"}|")?.evaluate(&data)?, data);
+        assert_eq!(jsonata("$ ~> |{\"b\": 1}|{\"c\": \"x\"}|")?.evaluate(&data)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_concatenation() -> Result<()> {
+        let data = serde_json::json!({"n": 2, "f": 1.5, "b": true, "a": [1, "x"], "o": {"k": null}});
+        assert_eq!(jsonata("\"n=\" & n & \", f=\" & f & \", b=\" & b")?.evaluate(&data)?, serde_json::json!("n=2, f=1.5, b=true"));
+        assert_eq!(jsonata("a & o & missing")?.evaluate(&data)?, serde_json::json!("[1,\"x\"]{\"k\":null}"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_lambda() -> Result<()> {
+        let data = serde_json::json!({"items": [1, 2, 3], "factor": 10});
+        let expression = jsonata("$map(items, function($v) { $v * 2 })")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([2.0, 4.0, 6.0]));
+
+        let expression = jsonata("$map(items, λ($v, $i) { $v * $i })")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([0.0, 2.0, 6.0]));
+
+        // The context of the body is the context of the definition
+        let expression = jsonata("$map(items, function($v) { $v * factor })")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([10.0, 20.0, 30.0]));
+
+        let expression = jsonata("$map(items, $sum)")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([1.0, 2.0, 3.0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_lambda_closure() -> Result<()> {
+        let data = serde_json::json!({"xs": [1, 2], "ys": [10, 20]});
+        let mut expression = jsonata("$map(xs, function($x) { $map(ys, function($y) { $x * $y + $offset }) })")?;
+        expression.bind("offset".into(), Binding::Value(serde_json::json!(1)));
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([[11.0, 21.0], [21.0, 41.0]]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_lambda_scopes_are_freed() -> Result<()> {
+        let data = serde_json::json!({"xs": [1, 2]});
+        let expression = jsonata("$map(xs, function($x) { $map(xs, function($y) { $x + $y }) })")?;
+        let references = expression.environment.references();
+        expression.evaluate(&data)?;
+        assert_eq!(expression.environment.references(), references);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_invoke_non_function() -> Result<()> {
+        let mut expression = jsonata("$a(1)")?;
+        expression.bind("a".into(), Binding::Value(serde_json::json!(5.0)));
+        assert_eq!(expression.evaluate(&serde_json::json!({})), Err(crate::Error::T1006));
+        assert_eq!(jsonata("$unknown(1)")?.evaluate(&serde_json::json!({})), Err(crate::Error::T1006));
+        assert_eq!(jsonata("$map([1], 2)")?.evaluate(&serde_json::json!({})), Err(crate::Error::T1006));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_block() -> Result<()> {
+        let data = serde_json::json!({"Price": 50});
+        let expression = jsonata("( $rate := 0.2; $net := Price * (1 - $rate); $net )")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(40.0));
+
+        let expression = jsonata("($double := function($v) { $v * 2 }; $map([1, 2], $double))")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([2.0, 4.0]));
+
+        let expression = jsonata("($fact := function($n) { $n <= 1 ? 1 : $n * $fact($n - 1) }; $fact(5))")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(120.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_block_scope() -> Result<()> {
+        let data = serde_json::json!({});
+        let expression = jsonata("[($x := 5; $x), $x]")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!([5.0]));
+
+        let expression = jsonata("($x := 1; ($x := 2); $x)")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(1.0));
+
+        // Assignments don't change the bindings of the expression between evaluations
+        let mut expression = jsonata("$count := $count + 1")?;
+        expression.bind("count".into(), Binding::Value(serde_json::json!(1)));
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(2.0));
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(2.0));
+        Ok(())
+    }
+
+    fn bind_string_functions(expression: &mut crate::Jsonata<serde_json::Value>) {
+        let string_function = |f: fn(&str) -> String| Function {
+            implementation: Box::new(move |args: Vec<serde_json::Value>| -> Result<serde_json::Value> {
+                Ok(serde_json::json!(f(args.first().and_then(|arg| arg.as_str()).unwrap_or_default())))
+            }),
+        };
+        expression.bind("trim".into(), Binding::Function(string_function(|s| s.trim().to_string())));
+        expression.bind("uppercase".into(), Binding::Function(string_function(|s| s.to_uppercase())));
+    }
+
+    #[test]
+    fn test_jsonata_chain() -> Result<()> {
+        let data = serde_json::json!({"Name": "  ada  ", "items": [1, 2]});
+        let mut expression = jsonata("Name ~> $trim() ~> $uppercase()")?;
+        bind_string_functions(&mut expression);
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!("ADA"));
+
+        let expression = jsonata("items ~> $map(function($v) { $v + 1 }) ~> $sum")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(5.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_chain_composition() -> Result<()> {
+        let data = serde_json::json!({"Name": "  ada  "});
+        let mut expression = jsonata("($normalize := $trim ~> $uppercase; $normalize(Name))")?;
+        bind_string_functions(&mut expression);
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!("ADA"));
+
+        assert_eq!(jsonata("Name ~> 2")?.evaluate(&data), Err(crate::Error::T2006));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_block_scope_is_freed() -> Result<()> {
+        let data = serde_json::json!({});
+        let expressions = [
+            "($fact := function($n) { $n <= 1 ? 1 : $n * $fact($n - 1) }; $fact(5))",
+            "($f := ($y := 2; function($x) { $x * $y }); $g := $f; $g(3))",
+            "($adder := function($a) { function($b) { $a + $b } }; $add1 := $adder(1); $add1(2))",
+            "$twice := function($x) { $x * 2 }",
+        ];
+        for source in expressions {
+            let expression = jsonata(source)?;
+            let references = expression.environment.references();
+            expression.evaluate(&data)?;
+            assert_eq!(expression.environment.references(), references, "{source}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_partial_application() -> Result<()> {
+        let data = serde_json::json!({"names": ["ada", "bob"]});
+        let mut expression = jsonata("$map(names, $join(?, \"!\"))")?;
+        expression.bind("join".into(), Binding::Function(Function {
+            implementation: Box::new(|args: Vec<serde_json::Value>| -> Result<serde_json::Value> {
+                let strings: Vec<&str> = args.iter().filter_map(|arg| arg.as_str()).collect();
+                Ok(serde_json::json!(strings.concat()))
+            }),
+        }));
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(["ada!", "bob!"]));
+
+        let expression = jsonata("($sub := function($a, $b) { $a - $b }; $from10 := $sub(10, ?); $from10(3))")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(7.0));
+
+        let expression = jsonata("($sub := function($a, $b) { $a - $b }; 3 ~> $sub(?, 1))")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(2.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_partial_application_of_non_function() -> Result<()> {
+        let data = serde_json::json!({});
+        assert_eq!(jsonata("$unknown(?, 1)")?.evaluate(&data), Err(crate::Error::T1008));
+        assert_eq!(jsonata("($x := 1; $x(?))")?.evaluate(&data), Err(crate::Error::T1008));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_call_on_expression() -> Result<()> {
+        let data = serde_json::json!({"x": 3});
+        let expression = jsonata("function($x) { $x * 2 }(x)")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(6.0));
+
+        let expression = jsonata("($adder := function($a) { function($b) { $a + $b } }; $adder(1)(x))")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(4.0));
+
+        let expression = jsonata("($adder := function($a) { function($b) { $a + $b } }; x ~> $adder(1)())")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(4.0));
+
+        let expression = jsonata("($sub := function($a) { function($b, $c) { $a - $b - $c } }; $sub(10)(?, 1)(x))")?;
+        assert_eq!(expression.evaluate(&data)?, serde_json::json!(6.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonata_call_on_non_function() -> Result<()> {
+        let data = serde_json::json!({"x": 3});
+        assert_eq!(jsonata("(x)(1)")?.evaluate(&data), Err(crate::Error::T1006));
+        assert_eq!(jsonata("substring(?, 1)")?.evaluate(&data), Err(crate::Error::T1007));
+        assert_eq!(jsonata("[x](?, 1)")?.evaluate(&data), Err(crate::Error::T1008));
+        Ok(())
+    }
+
     #[test]
     fn test_jsonata_buildin_function() -> Result<()> {
         let data = serde_json::json!({
@@ -0,0 +1,249 @@
+use jsonata_error::{Error, Result};
+use jsonata_expression::Expression;
+use std::rc::Rc;
+
+use crate::{environment::{Callable, Captures, Environment, Value}, evaluate::{evaluate, sequence}, JsonataData, Segment};
+
+/// The items or fields of a constructed array or object.
+type Parts<T> = Rc<[(Segment, Tracked<T>)]>;
+
+/// Data the pattern of a transform is evaluated as: the data, and where it is in the input of
+/// the transform, so the values the pattern matches can be found in the copy being modified.
+#[derive(Clone)]
+pub struct Tracked<T> {
+    data: T,
+    // None for data the pattern constructed
+    location: Option<Rc<[Segment]>>,
+    // The parts of constructed data keep their own locations
+    parts: Option<Parts<T>>,
+}
+
+impl<T: JsonataData + Clone> Tracked<T> {
+    fn new(data: T) -> Self {
+        Tracked { data, location: None, parts: None }
+    }
+
+    fn child(&self, segment: Segment, data: T) -> Self {
+        let location = self.location.as_ref().map(|location| {
+            let mut location = location.to_vec();
+            location.push(segment);
+            Rc::from(location)
+        });
+        Tracked { data, location, parts: None }
+    }
+
+    fn constructed(data: T, parts: Vec<(Segment, Tracked<T>)>) -> Self {
+        Tracked { data, location: None, parts: Some(Rc::from(parts)) }
+    }
+
+    /// The data, after a change that leaves its parts out of date.
+    fn changed(&mut self) -> &mut T {
+        self.location = None;
+        self.parts = None;
+        &mut self.data
+    }
+}
+
+impl<T: JsonataData + Clone + 'static> JsonataData for Tracked<T> {
+    fn get_field(&self, field: &str) -> Option<Self> {
+        match &self.parts {
+            Some(parts) => parts.iter().find(|(segment, _)| matches!(segment, Segment::Field(name) if name == field)).map(|(_, part)| part.clone()),
+            None => self.data.get_field(field).map(|data| self.child(Segment::Field(field.to_string()), data)),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        self.data.as_f64()
+    }
+
+    fn from_f64(value: f64) -> Self {
+        Tracked::new(T::from_f64(value))
+    }
+
+    fn is_array(&self) -> bool {
+        self.data.is_array()
+    }
+
+    fn as_array(&self) -> Option<Vec<Self>> {
+        if !self.data.is_array() {
+            return None;
+        }
+        match &self.parts {
+            Some(parts) => Some(parts.iter().map(|(_, part)| part.clone()).collect()),
+            None => self.data.as_array().map(|items| items
+                .into_iter()
+                .enumerate()
+                .map(|(index, item)| self.child(Segment::Index(index), item))
+                .collect()),
+        }
+    }
+
+    fn from_array(array: Vec<Self>) -> Self {
+        let data = T::from_array(array.iter().map(|item| item.data.clone()).collect());
+        Tracked::constructed(data, array.into_iter().enumerate().map(|(index, item)| (Segment::Index(index), item)).collect())
+    }
+
+    fn as_object(&self) -> Option<Vec<(String, Self)>> {
+        match &self.parts {
+            Some(_) if self.data.is_array() => None,
+            Some(parts) => Some(parts
+                .iter()
+                .filter_map(|(segment, part)| match segment {
+                    Segment::Field(name) => Some((name.clone(), part.clone())),
+                    Segment::Index(_) => None,
+                })
+                .collect()),
+            None => self.data.as_object().map(|entries| entries
+                .into_iter()
+                .map(|(name, data)| {
+                    let part = self.child(Segment::Field(name.clone()), data);
+                    (name, part)
+                })
+                .collect()),
+        }
+    }
+
+    fn from_object(entries: Vec<(String, Self)>) -> Self {
+        let data = T::from_object(entries.iter().map(|(name, part)| (name.clone(), part.data.clone())).collect());
+        Tracked::constructed(data, entries.into_iter().map(|(name, part)| (Segment::Field(name), part)).collect())
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        self.data.as_bool()
+    }
+
+    fn from_bool(value: bool) -> Self {
+        Tracked::new(T::from_bool(value))
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        self.data.as_str()
+    }
+
+    fn from_string(value: String) -> Self {
+        Tracked::new(T::from_string(value))
+    }
+
+    fn is_null(&self) -> bool {
+        self.data.is_null()
+    }
+
+    fn null() -> Self {
+        Tracked::new(T::null())
+    }
+
+    fn deep_clone(&self) -> Self {
+        Tracked::new(self.data.deep_clone())
+    }
+
+    fn modify(&mut self, location: &[Segment], f: &mut dyn FnMut(&mut Self) -> Result<()>) -> Result<()> {
+        self.changed().modify(location, &mut |data| {
+            let mut target = Tracked::new(std::mem::replace(data, T::null()));
+            let result = f(&mut target);
+            *data = target.data;
+            result
+        })
+    }
+
+    fn merge(&mut self, entries: Vec<(String, Self)>) {
+        self.changed().merge(entries.into_iter().map(|(name, part)| (name, part.data)).collect());
+    }
+
+    fn remove_field(&mut self, field: &str) {
+        self.changed().remove_field(field);
+    }
+
+    fn transform(pattern: &Expression, update: &Expression, delete: Option<&Expression>, input: &Self, root: &Self, environment: &Environment<Self>) -> Result<Self> {
+        // A transform in a pattern modifies a copy of its input like any other, which is not
+        // part of the input of the outer transform
+        let captures = Captures::new();
+        let environment = convert(environment, untrack).evaluation(&captures);
+        T::transform(pattern, update, delete, &input.data, &root.data, &environment).map(Tracked::new)
+    }
+}
+
+fn track<T: JsonataData + Clone + 'static>(value: Value<T>) -> Value<Tracked<T>> {
+    match value {
+        Value::Data(data) => Value::Data(Tracked::new(data)),
+        Value::Function(function) => Value::Function(Rc::new(Callable {
+            arity: function.arity,
+            implementation: Box::new(move |args| {
+                let args = args.into_iter().map(|arg| arg.map(untrack)).collect();
+                (function.implementation)(args).map(|result| result.map(track))
+            }),
+        })),
+    }
+}
+
+fn untrack<T: JsonataData + Clone + 'static>(value: Value<Tracked<T>>) -> Value<T> {
+    match value {
+        Value::Data(tracked) => Value::Data(tracked.data),
+        Value::Function(function) => Value::Function(Rc::new(Callable {
+            arity: function.arity,
+            implementation: Box::new(move |args| {
+                let args = args.into_iter().map(|arg| arg.map(track)).collect();
+                (function.implementation)(args).map(|result| result.map(untrack))
+            }),
+        })),
+    }
+}
+
+/// An environment with the variables of `environment`, converted by `f`.
+fn convert<A, B>(environment: &Environment<A>, f: fn(Value<A>) -> Value<B>) -> Environment<B>
+where
+    A: JsonataData + Clone + 'static,
+    B: JsonataData + Clone + 'static,
+{
+    let converted = Environment::new();
+    for (name, value) in environment.variables() {
+        converted.bind_value(name, value.map(f));
+    }
+    converted
+}
+
+/// Applies `| pattern | update, delete |` to a copy of `input`.
+///
+/// The pattern is evaluated like any other expression, against the input tracking where each
+/// value is in it. The values it matches are then merged with the object `update` evaluates to
+/// and lose the fields `delete` evaluates to, in the copy.
+pub(crate) fn transform<T: JsonataData + Clone + 'static>(pattern: &Expression, update: &Expression, delete: Option<&Expression>, input: &T, root: &T, environment: &Environment<T>) -> Result<T> {
+    let captures = Captures::new();
+    let tracking = convert(environment, track).evaluation(&captures);
+    let tracked = Tracked { data: input.clone(), location: Some(Rc::from([])), parts: None };
+    let matches = evaluate(pattern, &tracked, &Tracked::new(root.clone()), &tracking)?;
+
+    let mut result = input.deep_clone();
+    // The fields an earlier match replaced or removed, what was in them is no longer in the copy
+    let mut replaced: Vec<Vec<Segment>> = vec![];
+    for location in matches.into_iter().flat_map(sequence).filter_map(|tracked| tracked.location) {
+        if replaced.iter().any(|field| location.starts_with(field)) {
+            continue;
+        }
+        let mut fields = vec![];
+        result.modify(&location, &mut |target| {
+            if let Some(update) = evaluate(update, target, root, environment)? {
+                let entries = update.as_object().ok_or(Error::T2011)?;
+                fields.extend(entries.iter().map(|(name, _)| name.clone()));
+                target.merge(entries);
+            }
+            if let Some(delete) = delete.map(|delete| evaluate(delete, target, root, environment)).transpose()?.flatten() {
+                let names = sequence(delete)
+                    .iter()
+                    .map(|name| name.as_str().map(str::to_string))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(Error::T2012)?;
+                for name in &names {
+                    target.remove_field(name);
+                }
+                fields.extend(names);
+            }
+            Ok(())
+        })?;
+        replaced.extend(fields.into_iter().map(|name| {
+            let mut field = location.to_vec();
+            field.push(Segment::Field(name));
+            field
+        }));
+    }
+    Ok(result)
+}
@@ -1,11 +1,84 @@
 
+/// A location in the source of an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    pub offset: usize, // byte offset from the start of the source
+    pub line: usize,   // 1-based
+    pub column: usize, // 1-based, counted in characters
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 // TODO: user "thiserror"?
 #[derive(Debug, Clone, PartialEq)]
 pub enum Error {
+    T1003, // Key in object structure must evaluate to a string; got: {{value}}
+    T1006, // Attempted to invoke a non-function
+    T1007, // Attempted to partially apply a non-function. Did you mean ${{{token}}}?
+    T1008, // Attempted to partially apply a non-function
     T2001,
     T2002,
-    S0102,
-    D1002, // Number out of range: {{token}}
+    T2006, // The right side of the function application operator ~> must be a function
+    T2007, // Type mismatch when comparing values {{value}} and {{value2}} in order-by clause
+    T2008, // The expressions within an order-by clause must evaluate to numeric or string values
+    T2009, // The values {{value}} and {{value2}} either side of operator {{token}} must be of the same data type
+    T2010, // The expressions either side of operator {{token}} must evaluate to numeric or string values
+    T2011, // The insert/update clause of the transform expression must evaluate to an object: {{value}}
+    T2012, // The delete clause of the transform expression must evaluate to a string or array of strings: {{value}}
+    S0101(Position), // String literal must be terminated by a matching quote
+    S0102(Position),
+    S0103(Position), // Unsupported escape sequence: \{{token}}
+    S0104(Position), // The escape sequence \u must be followed by 4 hex digits
+    S0105(Position), // Quoted property name must be terminated with a backquote (`)
+    S0106(Position), // Comment has no closing tag
+    S0201(Position), // Syntax error: {{token}}
+    S0202(Position), // Expected {{value}}, got {{token}}
+    S0203(Position), // Expected {{value}} before end of expression
+    S0204(Position), // Unknown operator: {{token}}
+    S0208(Position), // Parameter {{value}} of function definition must be a variable name (start with $)
+    S0211(Position), // The symbol {{token}} cannot be used as a unary operator
+    S0212(Position), // The left side of := must be a variable name (start with $)
+    S0214(Position), // The right side of {{token}} must be a variable name (start with $)
+    S0217(Position), // The object representing the 'parent' cannot be derived from this expression
+    S0301(Position), // Empty regular expressions are not allowed
+    S0302(Position), // No terminating / in regular expression
+    S0500, // Attempted to evaluate an expression containing syntax error(s)
+    D1002(Position), // Number out of range: {{token}}
+    D1009, // Multiple key definitions evaluate to same key: {{value}}
+    D2003, // The left side of the range operator (..) must evaluate to an integer
+    D2004, // The right side of the range operator (..) must evaluate to an integer
+    D2014, // The size of the sequence allocated by the range operator (..) must not exceed 1e7
+}
+
+impl Error {
+    /// Where in the expression the error was found, for errors raised while parsing.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Error::S0101(position)
+            | Error::S0102(position)
+            | Error::S0103(position)
+            | Error::S0104(position)
+            | Error::S0105(position)
+            | Error::S0106(position)
+            | Error::S0201(position)
+            | Error::S0202(position)
+            | Error::S0203(position)
+            | Error::S0204(position)
+            | Error::S0208(position)
+            | Error::S0211(position)
+            | Error::S0212(position)
+            | Error::S0214(position)
+            | Error::S0217(position)
+            | Error::S0301(position)
+            | Error::S0302(position)
+            | Error::D1002(position) => Some(*position),
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
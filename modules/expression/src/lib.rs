@@ -1,8 +1,13 @@
+use std::rc::Rc;
+
 #[derive(Debug)]
 pub enum Atom {
     Number(f64),
     Name(String),
     String(String),
+    Bool(bool),
+    Null,
+    Regex { pattern: String, flags: String },
     End,
 }
 
@@ -12,6 +17,9 @@ impl std::fmt::Display for Atom {
             Self::Number(i) => write!(f, "{i}"),
             Self::Name(n) => write!(f, "{n}"),
             Self::String(n) => write!(f, "\"{n}\""),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Null => write!(f, "null"),
+            Self::Regex { pattern, flags } => write!(f, "/{pattern}/{flags}"),
             Self::End => write!(f, ""),
         }
     }
@@ -40,6 +48,64 @@ impl std::fmt::Display for NumericBinaryOperator {
 }
 
 
+#[derive(Debug)]
+pub enum BooleanBinaryOperator {
+    And,
+    Or,
+}
+
+impl std::fmt::Display for BooleanBinaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BooleanBinaryOperator::And => write!(f, "and"),
+            BooleanBinaryOperator::Or => write!(f, "or"),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ComparisonOperator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    In,
+}
+
+impl std::fmt::Display for ComparisonOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComparisonOperator::Equal => write!(f, "="),
+            ComparisonOperator::NotEqual => write!(f, "!="),
+            ComparisonOperator::Less => write!(f, "<"),
+            ComparisonOperator::LessEqual => write!(f, "<="),
+            ComparisonOperator::Greater => write!(f, ">"),
+            ComparisonOperator::GreaterEqual => write!(f, ">="),
+            ComparisonOperator::In => write!(f, "in"),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+impl std::fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SortOrder::Ascending => write!(f, "<"),
+            SortOrder::Descending => write!(f, ">"),
+        }
+    }
+}
+
+
 #[derive(Debug)]
 pub enum NumericUnaryOperator {
     Negate,
@@ -53,6 +119,17 @@ impl std::fmt::Display for NumericUnaryOperator {
     }
 }
 
+fn write_arguments(f: &mut std::fmt::Formatter<'_>, args: &[Expression]) -> std::fmt::Result {
+    write!(f, "(")?;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{arg}")?;
+    }
+    write!(f, ")")
+}
+
 #[derive(Debug)]
 pub enum Variable {
     Value,
@@ -63,13 +140,7 @@ impl std::fmt::Display for Variable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Variable::Value => write!(f, ""),
-            Variable::Function(args) => {
-                let _ = write!(f, "(");
-                for arg in args {
-                    let _ = write!(f, "{arg}");
-                }
-                write!(f, ")")
-            },
+            Variable::Function(args) => write_arguments(f, args),
         }
     }
 }
@@ -78,9 +149,56 @@ impl std::fmt::Display for Variable {
 pub enum Expression {
     Atom(Atom),
     BinaryNumeric(NumericBinaryOperator, Box<Expression>, Box<Expression>),
+    BinaryBoolean(BooleanBinaryOperator, Box<Expression>, Box<Expression>),
+    Comparison(ComparisonOperator, Box<Expression>, Box<Expression>),
+    Concatenate(Box<Expression>, Box<Expression>),
+    Condition(Box<Expression>, Box<Expression>, Option<Box<Expression>>),
+    Default(Box<Expression>, Box<Expression>),
+    Coalesce(Box<Expression>, Box<Expression>),
+    Chain(Box<Expression>, Box<Expression>),
     Path(Box<Expression>, Box<Expression>),
+    Predicate(Box<Expression>, Box<Expression>),
+    ArrayConstructor(Vec<Expression>),
+    ObjectConstructor(Vec<(Expression, Expression)>),
+    GroupBy(Box<Expression>, Vec<(Expression, Expression)>),
+    OrderBy(Box<Expression>, Vec<(Expression, SortOrder)>),
+    /// A step that binds each item to a variable, `step@$v`, without moving the context.
+    Focus(Box<Expression>, String),
+    /// A step that binds the position of each item to a variable, `step#$i`.
+    Index(Box<Expression>, String),
+    Range(Box<Expression>, Box<Expression>),
     Unary(NumericUnaryOperator, Box<Expression>),
     Variable(String, Variable),
+    /// A call on an expression other than a variable, such as a lambda or the result of a call.
+    Call(Box<Expression>, Vec<Expression>),
+    Assign(String, Box<Expression>),
+    /// Expressions separated by `;` in parentheses, evaluated in a scope of their own.
+    Block(Vec<Expression>),
+    /// A function definition, the body is shared with the function values created from it.
+    /// The signature is kept as written and not yet checked against the arguments.
+    Lambda { params: Vec<String>, signature: Option<String>, body: Rc<Expression> },
+    /// The transform operator `| pattern | update, delete |`, a function that modifies a copy of
+    /// its argument. The clauses are shared with the function values created from it.
+    Transform { pattern: Rc<Expression>, update: Rc<Expression>, delete: Option<Rc<Expression>> },
+    Context,
+    Root,
+    Wildcard,
+    Descendant,
+    /// A `?` argument, which makes a function call a partial application.
+    Placeholder,
+    /// The parent operator `%`, with its byte offset in the source for errors found after parsing.
+    Parent(usize),
+}
+
+fn write_pairs(f: &mut std::fmt::Formatter<'_>, pairs: &[(Expression, Expression)]) -> std::fmt::Result {
+    write!(f, "{{")?;
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{key}: {value}")?;
+    }
+    write!(f, "}}")
 }
 
 impl std::fmt::Display for Expression {
@@ -88,9 +206,78 @@ impl std::fmt::Display for Expression {
         match self {
             Expression::Atom(i) => write!(f, "{i}"),
             Expression::BinaryNumeric(op, lhs, rhs) => write!(f, "({} {} {})", op, lhs, rhs),
+            Expression::BinaryBoolean(op, lhs, rhs) => write!(f, "({} {} {})", op, lhs, rhs),
+            Expression::Comparison(op, lhs, rhs) => write!(f, "({} {} {})", op, lhs, rhs),
+            Expression::Concatenate(lhs, rhs) => write!(f, "(& {} {})", lhs, rhs),
+            Expression::Condition(condition, then, Some(otherwise)) => write!(f, "(? {} {} {})", condition, then, otherwise),
+            Expression::Condition(condition, then, None) => write!(f, "(? {} {})", condition, then),
+            Expression::Default(lhs, rhs) => write!(f, "(?: {} {})", lhs, rhs),
+            Expression::Coalesce(lhs, rhs) => write!(f, "(?? {} {})", lhs, rhs),
+            Expression::Chain(lhs, rhs) => write!(f, "(~> {} {})", lhs, rhs),
             Expression::Path(lhs, rhs) => write!(f, "(. {} {})", lhs, rhs),
+            Expression::Predicate(lhs, rhs) => write!(f, "([] {} {})", lhs, rhs),
+            Expression::ArrayConstructor(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            },
+            Expression::ObjectConstructor(pairs) => write_pairs(f, pairs),
+            Expression::GroupBy(lhs, pairs) => {
+                write!(f, "({{}} {} ", lhs)?;
+                write_pairs(f, pairs)?;
+                write!(f, ")")
+            },
+            Expression::OrderBy(lhs, terms) => {
+                write!(f, "(^ {}", lhs)?;
+                for (expression, order) in terms {
+                    write!(f, " {order}{expression}")?;
+                }
+                write!(f, ")")
+            },
+            Expression::Focus(lhs, name) => write!(f, "(@ {} ${})", lhs, name),
+            Expression::Index(lhs, name) => write!(f, "(# {} ${})", lhs, name),
+            Expression::Range(lhs, rhs) => write!(f, "(.. {} {})", lhs, rhs),
             Expression::Unary(op, lhs) => write!(f, "({} {})", op, lhs),
             Expression::Variable(name, var) => write!(f, "${}{}", name, var),
+            Expression::Call(callee, args) => {
+                write!(f, "{callee}")?;
+                write_arguments(f, args)
+            },
+            Expression::Assign(name, rhs) => write!(f, "(:= ${} {})", name, rhs),
+            Expression::Block(expressions) => {
+                write!(f, "(;")?;
+                for expression in expressions {
+                    write!(f, " {expression}")?;
+                }
+                write!(f, ")")
+            },
+            Expression::Lambda { params, signature, body } => {
+                write!(f, "(function (")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "${param}")?;
+                }
+                write!(f, ") ")?;
+                if let Some(signature) = signature {
+                    write!(f, "{signature} ")?;
+                }
+                write!(f, "{body})")
+            },
+            Expression::Transform { pattern, update, delete: Some(delete) } => write!(f, "(| {} {} {})", pattern, update, delete),
+            Expression::Transform { pattern, update, delete: None } => write!(f, "(| {} {})", pattern, update),
+            Expression::Context => write!(f, "$"),
+            Expression::Root => write!(f, "$$"),
+            Expression::Wildcard => write!(f, "*"),
+            Expression::Descendant => write!(f, "**"),
+            Expression::Placeholder => write!(f, "?"),
+            Expression::Parent(_) => write!(f, "%"),
         }
     }
 }
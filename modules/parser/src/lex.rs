@@ -1,26 +1,111 @@
-use crate::token::{Operator, Token};
-use jsonata_error::{Error, Result};
+use crate::token::{Operator, Span, SpannedToken, Token};
+use jsonata_error::{Error, Position, Result};
+use std::borrow::Cow;
 
+/// Characters that start an operator and therefore end a name.
+const OPERATOR_CHARS: &str = ".[]{}(),@#;:?+-*/%|=<>^&!~";
+
+/// Names and variables run until whitespace or an operator character.
+fn is_name_char(c: char) -> bool {
+    !c.is_whitespace() && !OPERATOR_CHARS.contains(c)
+}
+
+/// Splits an expression into tokens.
+///
+/// Iterating yields the tokens alone, `spanned` and `next_spanned` also give
+/// the location of each token in the source.
 pub struct Lexer<'a> {
     source: &'a str,
     position: usize,
-    peeked: Option<Result<Token<'a>>>,
+    peeked: Option<(Result<Token<'a>>, Span)>,
+    keep_comments: bool,
+    // Byte offsets where each line of the source starts
+    line_starts: Vec<usize>,
+    token_start: usize,
+    span: Span,
 }
 
 impl<'a> Lexer<'a> {
 
     pub fn new (source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
         Self {
             source,
             position: 0,
             peeked: None,
+            keep_comments: false,
+            line_starts,
+            token_start: 0,
+            span: Span::default(),
         }
     }
 
+    /// Emit block comments as `Token::Comment` instead of skipping them like whitespace.
+    pub fn keep_comments (mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
+    /// Iterate over the tokens together with their spans.
+    pub fn spanned (mut self) -> impl Iterator<Item = Result<SpannedToken<'a>>> {
+        std::iter::from_fn(move || self.next_spanned())
+    }
+
+    /// The next token and its span.
+    pub fn next_spanned (&mut self) -> Option<Result<SpannedToken<'a>>> {
+        let token = self.next_token()?;
+        Some(token.map(|token| SpannedToken { token, span: self.span }))
+    }
+
+    /// The span of the token last returned, or an empty span at the end of the
+    /// source once all tokens have been returned.
+    pub fn span (&self) -> Span {
+        self.span
+    }
+
+    /// The span of the next token, without consuming it.
+    pub fn peek_span (&mut self) -> Span {
+        self.peek();
+        match &self.peeked {
+            Some((_, span)) => *span,
+            None => {
+                let end = self.locate(self.position);
+                Span { start: end, end }
+            },
+        }
+    }
+
+    /// Line and column of a byte offset in the source.
+    pub fn locate (&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let line_start = self.line_starts[line];
+        Position {
+            offset,
+            line: line + 1,
+            column: self.source[line_start..offset].chars().count() + 1,
+        }
+    }
+
+    /// The source text covered by a span.
+    pub fn text (&self, span: Span) -> &'a str {
+        &self.source[span.start.offset..span.end.offset]
+    }
+
+    /// An error located at the start of the token being lexed.
+    fn error (&self, code: fn(Position) -> Error) -> Error {
+        code(self.locate(self.token_start))
+    }
+
     fn peek_char (&self) -> Option<char> {
         self.source[self.position..].chars().next()
     }
 
+    fn peek_nth_char (&self, n: usize) -> Option<char> {
+        self.source[self.position..].chars().nth(n)
+    }
+
     fn advance (&mut self) -> Option<char> {
         if let Some(c) = self.peek_char() {
             self.position += c.len_utf8();
@@ -30,6 +115,15 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    fn next_char_if (&mut self, expected: char) -> bool {
+        if self.peek_char() == Some(expected) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
     fn advance_while<F>(&mut self, predicate: F)
         where 
         F: Fn(char) -> bool,
@@ -44,14 +138,133 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Lexes a string literal delimited by `quote`, the opening quote has already been consumed.
+    fn string_literal (&mut self, quote: char) -> Result<Token<'a>> {
+        let start = self.position;
+        let mut text: Option<String> = None;
+        loop {
+            let segment = self.position;
+            self.advance_while(|c| c != quote && c != '\\');
+            let literal = &self.source[segment..self.position];
+            match self.advance() {
+                Some('\\') => {
+                    let text = text.get_or_insert_with(String::new);
+                    text.push_str(literal);
+                    let escaped = self.escape_sequence()?;
+                    text.push(escaped);
+                },
+                Some(_) => {
+                    return Ok(Token::String(match text {
+                        Some(mut text) => {
+                            text.push_str(literal);
+                            Cow::Owned(text)
+                        },
+                        None => Cow::Borrowed(&self.source[start..self.position - 1]),
+                    }));
+                },
+                None => return Err(self.error(Error::S0101)),
+            }
+        }
+    }
+
+    /// Decodes the escape sequence following a backslash in a string literal.
+    fn escape_sequence (&mut self) -> Result<char> {
+        let escaped = match self.advance() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let high = self.hex_code_unit()?;
+                if !(0xD800..0xDC00).contains(&high) {
+                    return char::from_u32(high).ok_or_else(|| self.error(Error::S0104));
+                }
+                // A high surrogate must be followed by an escaped low surrogate
+                if !self.source[self.position..].starts_with("\\u") {
+                    return Err(self.error(Error::S0104));
+                }
+                self.position += 2;
+                let low = self.hex_code_unit()?;
+                if !(0xDC00..0xE000).contains(&low) {
+                    return Err(self.error(Error::S0104));
+                }
+                let code_point = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                return char::from_u32(code_point).ok_or_else(|| self.error(Error::S0104));
+            },
+            Some(_) => return Err(self.error(Error::S0103)),
+            None => return Err(self.error(Error::S0101)),
+        };
+        Ok(escaped)
+    }
+
+    /// Reads the four hex digits of a `\u` escape sequence.
+    fn hex_code_unit (&mut self) -> Result<u32> {
+        let digits = self.source[self.position..].get(..4).ok_or_else(|| self.error(Error::S0104))?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error(Error::S0104));
+        }
+        self.position += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| self.error(Error::S0104))
+    }
+
+    /// Lexes a `/pattern/flags` regular expression, the opening slash has already been consumed.
+    fn regex (&mut self) -> Result<Token<'a>> {
+        let start = self.position;
+        let mut depth = 0;
+        let mut escaped = false;
+        while let Some(c) = self.advance() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                '/' if depth == 0 => {
+                    let pattern = &self.source[start..self.position - 1];
+                    if pattern.is_empty() {
+                        return Err(self.error(Error::S0301));
+                    }
+                    let flags_start = self.position;
+                    self.advance_while(|c| c == 'i' || c == 'm');
+                    let flags = &self.source[flags_start..self.position];
+                    return Ok(Token::Regex { pattern, flags });
+                },
+                _ => {},
+            }
+        }
+        Err(self.error(Error::S0302))
+    }
+
+    /// Consumes a `/* ... */` block comment and returns the text between the delimiters.
+    fn comment (&mut self) -> Result<&'a str> {
+        let start = self.position + 2;
+        match self.source[start..].find("*/") {
+            Some(length) => {
+                self.position = start + length + 2;
+                Ok(&self.source[start..start + length])
+            },
+            None => {
+                self.position = self.source.len();
+                Err(self.error(Error::S0106))
+            },
+        }
+    }
+
     pub fn peek (&mut self) -> Option<&Result<Token<'a>>> {
-        if self.peeked.is_some() {
-            return self.peeked.as_ref();
+        if self.peeked.is_none() {
+            // Scanning moves the span, which must keep pointing at the last returned token
+            let span = self.span;
+            let token = self.next_token();
+            let peeked_span = std::mem::replace(&mut self.span, span);
+            self.peeked = Some((token?, peeked_span));
         }
-        self.peeked = self.next();
-        self.peeked.as_ref()
+        self.peeked.as_ref().map(|(token, _)| token)
     }
-    pub fn next_if<F>(&mut self, predicate: F) -> Option<Result<Token>>
+
+    pub fn next_if<F>(&mut self, predicate: F) -> Option<Result<Token<'a>>>
     where 
         F: Fn(&Token) -> bool,
     {
@@ -62,14 +275,64 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Lexes the next token where the parser expects an operand, there a `/` starts a
+    /// regular expression instead of being the division operator.
+    pub fn next_operand (&mut self) -> Option<Result<Token<'a>>> {
+        match self.next()? {
+            // The slash is a single character token, so the pattern starts right after it
+            Ok(Token::Operator(Operator::Slash)) => {
+                let token = self.regex();
+                self.span.end = self.locate(self.position);
+                Some(token)
+            },
+            token => Some(token),
+        }
+    }
+
     fn next_token (&mut self) -> Option<Result<Token<'a>>> {
-        if let Some(peeked) = self.peeked.take() {
+        if let Some((peeked, span)) = self.peeked.take() {
+            self.span = span;
             return Some(peeked)
         }
 
+        let token = self.scan();
+        let end = self.locate(self.position);
+        self.span = match token {
+            Some(_) => Span { start: self.locate(self.token_start), end },
+            None => Span { start: end, end },
+        };
+        token
+    }
+
+    fn scan (&mut self) -> Option<Result<Token<'a>>> {
         self.advance_while(|c| c.is_whitespace());
+        self.token_start = self.position;
+        while self.source[self.position..].starts_with("/*") {
+            let comment = match self.comment() {
+                Ok(comment) => comment,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.keep_comments {
+                return Some(Ok(Token::Comment(comment)));
+            }
+            self.advance_while(|c| c.is_whitespace());
+            self.token_start = self.position;
+        }
+
         let token = if let Some(c) = self.advance() {
             match c {
+                // multi char operators, longest match first
+                '.' if self.next_char_if('.') => Ok(Token::Operator(Operator::DotDot)),
+                '*' if self.next_char_if('*') => Ok(Token::Operator(Operator::StarStar)),
+                ':' if self.next_char_if('=') => Ok(Token::Operator(Operator::Assign)),
+                '!' if self.next_char_if('=') => Ok(Token::Operator(Operator::NotEqual)),
+                '<' if self.next_char_if('=') => Ok(Token::Operator(Operator::LessEqual)),
+                '>' if self.next_char_if('=') => Ok(Token::Operator(Operator::GreaterEqual)),
+                '~' if self.next_char_if('>') => Ok(Token::Operator(Operator::Chain)),
+                '?' if self.next_char_if('?') => Ok(Token::Operator(Operator::QuestionQuestion)),
+                '?' if self.next_char_if(':') => Ok(Token::Operator(Operator::QuestionColon)),
+                '!' | '~' => Err(self.error(Error::S0204)),
+
                 // single char operators
                 '+' => Ok(Token::Operator(Operator::Plus)),
                 '-' => Ok(Token::Operator(Operator::Minus)),
@@ -79,51 +342,95 @@ impl<'a> Lexer<'a> {
                 '.' => Ok(Token::Operator(Operator::Dot)),
                 ')' => Ok(Token::Operator(Operator::ParenRight)),
                 '(' => Ok(Token::Operator(Operator::ParenLeft)),
+                '[' => Ok(Token::Operator(Operator::BracketLeft)),
+                ']' => Ok(Token::Operator(Operator::BracketRight)),
+                '{' => Ok(Token::Operator(Operator::BraceLeft)),
+                '}' => Ok(Token::Operator(Operator::BraceRight)),
+                ',' => Ok(Token::Operator(Operator::Comma)),
+                ';' => Ok(Token::Operator(Operator::Semicolon)),
+                ':' => Ok(Token::Operator(Operator::Colon)),
+                '?' => Ok(Token::Operator(Operator::Question)),
+                '=' => Ok(Token::Operator(Operator::Equal)),
+                '<' => Ok(Token::Operator(Operator::Less)),
+                '>' => Ok(Token::Operator(Operator::Greater)),
+                '&' => Ok(Token::Operator(Operator::Ampersand)),
+                '|' => Ok(Token::Operator(Operator::Pipe)),
+                '@' => Ok(Token::Operator(Operator::At)),
+                '#' => Ok(Token::Operator(Operator::Hash)),
+                '^' => Ok(Token::Operator(Operator::Caret)),
 
                 '$' => {
                     let start = self.position;
-                    self.advance_while(|c| c.is_alphanumeric());
+                    self.advance_while(is_name_char);
                     let end = self.position;
-                    let text = &self.source[start..end];
-                    Ok(Token::Variable(text))
+                    match &self.source[start..end] {
+                        "" => Ok(Token::Context),
+                        "$" => Ok(Token::Root),
+                        text => Ok(Token::Variable(text)),
+                    }
                 },
 
                 // string literals
-                '"' => {
+                '"' | '\'' => self.string_literal(c),
+
+                // quoted names
+                '`' => {
                     let start = self.position;
-                    self.advance_while(|c| c != '"');
+                    self.advance_while(|c| c != '`');
                     let end = self.position;
-                    let text = &self.source[start..end];
-                    self.position += 1;
-                    Ok(Token::String(text))
+                    match self.advance() {
+                        Some(_) => Ok(Token::Name(&self.source[start..end])),
+                        None => Err(self.error(Error::S0105)),
+                    }
                 },
 
                 // numeric literals
-                // TODO: This is a placeholder implementation of numeric literal
-                // NOT compatible with the javascript implementation
-                // Needs tests to make sure
+                // Follows the JSON number grammar used by jsonata-js:
+                // -?(0|([1-9][0-9]*))(\.[0-9]+)?([Ee][-+]?[0-9]+)?
+                // The sign is lexed as a separate minus operator.
                 '0'..='9' => {
                     let start = self.position - 1;
-                    self.advance_while(|c| c.is_numeric());
+                    if c != '0' {
+                        self.advance_while(|c| c.is_ascii_digit());
+                    }
+                    if self.peek_char() == Some('.') && self.peek_nth_char(1).is_some_and(|c| c.is_ascii_digit()) {
+                        self.advance();
+                        self.advance_while(|c| c.is_ascii_digit());
+                    }
+                    if let Some('e' | 'E') = self.peek_char() {
+                        let digits_at = match self.peek_nth_char(1) {
+                            Some('+' | '-') => 2,
+                            _ => 1,
+                        };
+                        if self.peek_nth_char(digits_at).is_some_and(|c| c.is_ascii_digit()) {
+                            for _ in 0..digits_at {
+                                self.advance();
+                            }
+                            self.advance_while(|c| c.is_ascii_digit());
+                        }
+                    }
                     let end = self.position;
                     let text = &self.source[start..end];
-                    match text.parse() {
-                        Ok(literal) => Ok(Token::Number(literal)),
-                        Err(_) => Err(Error::S0102)
+                    match text.parse::<f64>() {
+                        Ok(literal) if literal.is_finite() => Ok(Token::Number(literal)),
+                        Ok(_) => Err(self.error(Error::D1002)),
+                        Err(_) => Err(self.error(Error::S0102)),
                     }
                 }
 
                 // names
                 _ => {
-                    let start = self.position - 1;
-                    self.advance_while(|c| c.is_alphanumeric());
+                    let start = self.position - c.len_utf8();
+                    self.advance_while(is_name_char);
                     let end = self.position;
                     let text = &self.source[start..end];
                     match text {
-                        "or" | "in" | "and" => todo!(),
-                        "true" => todo!(),
-                        "false" => todo!(),
-                        "null" => todo!(),
+                        "and" => Ok(Token::Operator(Operator::And)),
+                        "or" => Ok(Token::Operator(Operator::Or)),
+                        "in" => Ok(Token::Operator(Operator::In)),
+                        "true" => Ok(Token::Bool(true)),
+                        "false" => Ok(Token::Bool(false)),
+                        "null" => Ok(Token::Null),
                         text => {
                             Ok(Token::Name(text))
                         }
@@ -149,7 +456,7 @@ impl<'a> Iterator for Lexer<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Lexer, Operator, Result, Token};
+    use super::{Error, Lexer, Operator, Position, Result, Span, Token};
 
     #[test]
     fn test_lex_sum_fn() -> Result<()> {
@@ -173,11 +480,358 @@ mod tests {
         assert_eq!(tokens, [
             Token::Name("foo"),
             Token::Operator(Operator::Dot),
-            Token::String("bar"),
+            Token::String("bar".into()),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_string_single_quoted() -> Result<()> {
+        let lexer = Lexer::new("'it is' 'say \\\"hi\\\"' \"'\"");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::String("it is".into()),
+            Token::String("say \"hi\"".into()),
+            Token::String("'".into()),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_string_escapes() -> Result<()> {
+        let lexer = Lexer::new(r#""a\"b" "\n\t\r\b\f\/\\" "\u00e9x" "\ud83d\ude00" "é""#);
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::String("a\"b".into()),
+            Token::String("\n\t\r\u{8}\u{c}/\\".into()),
+            Token::String("éx".into()),
+            Token::String("😀".into()),
+            Token::String("é".into()),
         ]);
         Ok(())
     }
 
+    #[test]
+    fn test_lex_string_errors() {
+        let mut lexer = Lexer::new("\"abc");
+        assert!(matches!(lexer.next(), Some(Err(Error::S0101(_)))));
+
+        let mut lexer = Lexer::new("'abc\\");
+        assert!(matches!(lexer.next(), Some(Err(Error::S0101(_)))));
+
+        let mut lexer = Lexer::new(r#""\q""#);
+        assert!(matches!(lexer.next(), Some(Err(Error::S0103(_)))));
+
+        let mut lexer = Lexer::new(r#""\u12g4""#);
+        assert!(matches!(lexer.next(), Some(Err(Error::S0104(_)))));
+
+        let mut lexer = Lexer::new(r#""\ud83d""#);
+        assert!(matches!(lexer.next(), Some(Err(Error::S0104(_)))));
+    }
+
+    #[test]
+    fn test_lex_quoted_name() -> Result<()> {
+        let lexer = Lexer::new("`Product Name`.`order-id`");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Name("Product Name"),
+            Token::Operator(Operator::Dot),
+            Token::Name("order-id"),
+        ]);
+
+        let mut lexer = Lexer::new("`order-id");
+        assert!(matches!(lexer.next(), Some(Err(Error::S0105(_)))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_comments() -> Result<()> {
+        let lexer = Lexer::new("/* total */ price /* per unit */* /**/ 2 /* end */");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Name("price"),
+            Token::Operator(Operator::Star),
+            Token::Number(2.0),
+        ]);
+
+        let lexer = Lexer::new("price /* per unit */ * 2").keep_comments();
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Name("price"),
+            Token::Comment(" per unit "),
+            Token::Operator(Operator::Star),
+            Token::Number(2.0),
+        ]);
+
+        let mut lexer = Lexer::new("price /* per unit * 2");
+        assert_eq!(lexer.next(), Some(Ok(Token::Name("price"))));
+        assert!(matches!(lexer.next(), Some(Err(Error::S0106(_)))));
+        assert_eq!(lexer.next(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_keywords() -> Result<()> {
+        let lexer = Lexer::new("true and false or null in truthy");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Bool(true),
+            Token::Operator(Operator::And),
+            Token::Bool(false),
+            Token::Operator(Operator::Or),
+            Token::Null,
+            Token::Operator(Operator::In),
+            Token::Name("truthy"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_operators() -> Result<()> {
+        let lexer = Lexer::new("= != < <= > >= & ? : := ~> .. [ ] { } , ; | @ # ^ ** ?? ?: + - * / % . ( )");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        let text = tokens.iter().map(|token| match token {
+            Token::Operator(op) => op.to_string(),
+            token => panic!("Expected an operator, got {token:?}"),
+        }).collect::<Vec<_>>().join(" ");
+        assert_eq!(text, "= != < <= > >= & ? : := ~> .. [ ] { } , ; | @ # ^ ** ?? ?: + - * / % . ( )");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_operators_longest_match() -> Result<()> {
+        let lexer = Lexer::new("a...b***c:=:d?:?e~>f<=>=g");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Name("a"),
+            Token::Operator(Operator::DotDot),
+            Token::Operator(Operator::Dot),
+            Token::Name("b"),
+            Token::Operator(Operator::StarStar),
+            Token::Operator(Operator::Star),
+            Token::Name("c"),
+            Token::Operator(Operator::Assign),
+            Token::Operator(Operator::Colon),
+            Token::Name("d"),
+            Token::Operator(Operator::QuestionColon),
+            Token::Operator(Operator::Question),
+            Token::Name("e"),
+            Token::Operator(Operator::Chain),
+            Token::Name("f"),
+            Token::Operator(Operator::LessEqual),
+            Token::Operator(Operator::GreaterEqual),
+            Token::Name("g"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_unknown_operator() {
+        let mut lexer = Lexer::new("a ! b");
+        assert_eq!(lexer.next(), Some(Ok(Token::Name("a"))));
+        assert!(matches!(lexer.next(), Some(Err(Error::S0204(_)))));
+
+        let mut lexer = Lexer::new("~");
+        assert!(matches!(lexer.next(), Some(Err(Error::S0204(_)))));
+    }
+
+    #[test]
+    fn test_lex_names() -> Result<()> {
+        let lexer = Lexer::new("first_name $my_var café.名前 x1_y2 a$b");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Name("first_name"),
+            Token::Variable("my_var"),
+            Token::Name("café"),
+            Token::Operator(Operator::Dot),
+            Token::Name("名前"),
+            Token::Name("x1_y2"),
+            Token::Name("a$b"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_names_adjacent_to_operators() -> Result<()> {
+        let lexer = Lexer::new("Order[qty>1]{sku:price*2}$f(a,b)-c");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Name("Order"),
+            Token::Operator(Operator::BracketLeft),
+            Token::Name("qty"),
+            Token::Operator(Operator::Greater),
+            Token::Number(1.0),
+            Token::Operator(Operator::BracketRight),
+            Token::Operator(Operator::BraceLeft),
+            Token::Name("sku"),
+            Token::Operator(Operator::Colon),
+            Token::Name("price"),
+            Token::Operator(Operator::Star),
+            Token::Number(2.0),
+            Token::Operator(Operator::BraceRight),
+            Token::Variable("f"),
+            Token::Operator(Operator::ParenLeft),
+            Token::Name("a"),
+            Token::Operator(Operator::Comma),
+            Token::Name("b"),
+            Token::Operator(Operator::ParenRight),
+            Token::Operator(Operator::Minus),
+            Token::Name("c"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_names_keywords_need_full_match() -> Result<()> {
+        let lexer = Lexer::new("true_value android null_count");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Name("true_value"),
+            Token::Name("android"),
+            Token::Name("null_count"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_context_and_root() -> Result<()> {
+        let lexer = Lexer::new("$.qty $$.rate $$$ $[0]");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Context,
+            Token::Operator(Operator::Dot),
+            Token::Name("qty"),
+            Token::Root,
+            Token::Operator(Operator::Dot),
+            Token::Name("rate"),
+            Token::Variable("$$"),
+            Token::Context,
+            Token::Operator(Operator::BracketLeft),
+            Token::Number(0.0),
+            Token::Operator(Operator::BracketRight),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_regex() -> Result<()> {
+        let mut lexer = Lexer::new(r"/^a[/]b\/c$/im / 2");
+        assert_eq!(lexer.next_operand(), Some(Ok(Token::Regex { pattern: r"^a[/]b\/c$", flags: "im" })));
+        assert_eq!(lexer.next(), Some(Ok(Token::Operator(Operator::Slash))));
+        assert_eq!(lexer.next_operand(), Some(Ok(Token::Number(2.0))));
+        assert_eq!(lexer.next(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_regex_after_peek() -> Result<()> {
+        let mut lexer = Lexer::new("( /a/)");
+        assert_eq!(lexer.next(), Some(Ok(Token::Operator(Operator::ParenLeft))));
+        assert_eq!(lexer.peek(), Some(&Ok(Token::Operator(Operator::Slash))));
+        assert_eq!(lexer.next_operand(), Some(Ok(Token::Regex { pattern: "a", flags: "" })));
+        assert_eq!(lexer.next(), Some(Ok(Token::Operator(Operator::ParenRight))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_division_after_wildcard() -> Result<()> {
+        // Only the parser knows that `*` is an operand here, so `/ 2` must stay a division
+        let mut lexer = Lexer::new("Address.* / 2");
+        assert_eq!(lexer.next_operand(), Some(Ok(Token::Name("Address"))));
+        assert_eq!(lexer.next(), Some(Ok(Token::Operator(Operator::Dot))));
+        assert_eq!(lexer.next_operand(), Some(Ok(Token::Operator(Operator::Star))));
+        assert_eq!(lexer.next(), Some(Ok(Token::Operator(Operator::Slash))));
+        assert_eq!(lexer.next_operand(), Some(Ok(Token::Number(2.0))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_regex_errors() {
+        let mut lexer = Lexer::new("//");
+        assert!(matches!(lexer.next_operand(), Some(Err(Error::S0301(_)))));
+
+        let mut lexer = Lexer::new("/abc");
+        assert!(matches!(lexer.next_operand(), Some(Err(Error::S0302(_)))));
+
+        let mut lexer = Lexer::new("/a(/)");
+        assert!(matches!(lexer.next_operand(), Some(Err(Error::S0302(_)))));
+    }
+
+    #[test]
+    fn test_lex_spans() -> Result<()> {
+        let lexer = Lexer::new("$sum(\n  \"é\" & `a b`)");
+        let spans = lexer.spanned()
+            .map(|token| token.map(|token| (token.span.start, token.span.end)))
+            .collect::<Result<Vec<_>>>()?;
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(spans, [
+            (position(0, 1, 1), position(4, 1, 5)),
+            (position(4, 1, 5), position(5, 1, 6)),
+            (position(8, 2, 3), position(12, 2, 6)),
+            (position(13, 2, 7), position(14, 2, 8)),
+            (position(15, 2, 9), position(20, 2, 14)),
+            (position(20, 2, 14), position(21, 2, 15)),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_span_after_peek() {
+        let mut lexer = Lexer::new("a + b");
+        lexer.next();
+        assert_eq!(lexer.peek_span().start.column, 3);
+        assert_eq!(lexer.span().start.column, 1);
+        lexer.next();
+        assert_eq!(lexer.span().start.column, 3);
+        lexer.next();
+        assert_eq!(lexer.next(), None);
+        let end = Position { offset: 5, line: 1, column: 6 };
+        assert_eq!(lexer.span(), Span { start: end, end });
+    }
+
+    #[test]
+    fn test_lex_regex_span() {
+        let mut lexer = Lexer::new("x ~> /ab/i");
+        lexer.next();
+        lexer.next();
+        lexer.peek();
+        assert!(matches!(lexer.next_operand(), Some(Ok(Token::Regex { .. }))));
+        let span = lexer.span();
+        assert_eq!((span.start.column, span.end.column), (6, 11));
+
+        let mut lexer = Lexer::new("x ~> /ab");
+        lexer.next();
+        lexer.next();
+        assert_eq!(lexer.next_operand(), Some(Err(Error::S0302(Position { offset: 5, line: 1, column: 6 }))));
+    }
+
+    #[test]
+    fn test_lex_span_after_peek_at_end() {
+        let mut lexer = Lexer::new("a + b  ");
+        lexer.next();
+        lexer.next();
+        lexer.next();
+        let last = lexer.span();
+        assert_eq!(lexer.peek(), None);
+        assert_eq!(lexer.span(), last);
+        let end = Position { offset: 7, line: 1, column: 8 };
+        assert_eq!(lexer.peek_span(), Span { start: end, end });
+        assert_eq!(lexer.span(), last);
+        assert_eq!(lexer.next(), None);
+        assert_eq!(lexer.span(), Span { start: end, end });
+    }
+
+    #[test]
+    fn test_lex_error_positions() {
+        let position = |source| match Lexer::new(source).find_map(|token| token.err()) {
+            Some(error) => error.position(),
+            None => None,
+        };
+        assert_eq!(position("a &\n  \"abc"), Some(Position { offset: 6, line: 2, column: 3 }));
+        assert_eq!(position("x + /* y"), Some(Position { offset: 4, line: 1, column: 5 }));
+        assert_eq!(position("é ! b"), Some(Position { offset: 3, line: 1, column: 3 }));
+    }
+
     #[test]
     fn test_lex_prefix_number() -> Result<()> {
         let lexer = Lexer::new("-1");
@@ -239,30 +893,53 @@ mod tests {
         Ok(())
     }
 
-    #[ignore = "Deciaml support not implementet yet"]
     #[test]
     fn test_lex_numeric_decimal() -> Result<()> {
-        let lexer = Lexer::new("1.1 2.2 3");
+        let lexer = Lexer::new("1.1 2.2 3 0.25");
         let tokens = lexer.collect::<Result<Vec<Token>>>()?;
         assert_eq!(tokens, [
             Token::Number(1.1),
             Token::Number(2.2),
             Token::Number(3.0),
+            Token::Number(0.25),
         ]);
         Ok(())
     }
 
-    #[ignore = "Exponential support not implementet yet"]
     #[test]
     fn test_lex_numeric_exponentail() -> Result<()> {
-        // JS: Number.parseFloat(123000).toExponential(2) -> "23e+5"
-        let lexer = Lexer::new("23e+5");
+        // JS: Number.parseFloat(123000).toExponential(2) -> "1.23e+5"
+        let lexer = Lexer::new("1.23e+5 23e+5 5E-1 1e2");
         let tokens = lexer.collect::<Result<Vec<Token>>>()?;
         assert_eq!(tokens, [
             Token::Number(123000.0),
+            Token::Number(2300000.0),
+            Token::Number(0.5),
+            Token::Number(100.0),
         ]);
         Ok(())
     }
 
+    #[test]
+    fn test_lex_numeric_partial_match() -> Result<()> {
+        // Only the longest prefix matching the number grammar is consumed
+        let lexer = Lexer::new("1.a 2e 01");
+        let tokens = lexer.collect::<Result<Vec<Token>>>()?;
+        assert_eq!(tokens, [
+            Token::Number(1.0),
+            Token::Operator(Operator::Dot),
+            Token::Name("a"),
+            Token::Number(2.0),
+            Token::Name("e"),
+            Token::Number(0.0),
+            Token::Number(1.0),
+        ]);
+        Ok(())
+    }
 
+    #[test]
+    fn test_lex_numeric_out_of_range() {
+        let mut lexer = Lexer::new("1e309");
+        assert!(matches!(lexer.next(), Some(Err(Error::D1002(_)))));
+    }
 }
@@ -2,9 +2,12 @@ use jsonata_error::Result;
 use jsonata_expression::Expression;
 
 mod lex;
-use lex::Lexer;
+pub use lex::Lexer;
 
 mod token;
+pub use token::{Operator, Span, SpannedToken, Token};
+pub use jsonata_error::Position;
+
 mod parse;
 
 pub struct Parser<'a> {
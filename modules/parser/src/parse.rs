@@ -1,70 +1,296 @@
-use jsonata_error::Result;
-use jsonata_expression::{NumericBinaryOperator, NumericUnaryOperator, Variable};
+use jsonata_error::{Error, Result};
+use jsonata_expression::{BooleanBinaryOperator, ComparisonOperator, NumericBinaryOperator, NumericUnaryOperator, SortOrder, Variable};
 use jsonata_expression::{Expression, Atom};
 use crate::Lexer;
-use crate::token::{Operator, Token};
+use crate::token::{Operator, Span, Token};
+use std::rc::Rc;
 
 // https://matklad.github.io/2020/04/13/simple-but-powerful-pratt-parsing.html
 
-fn prefix_binding_power(op: &Operator) -> ((), u8) {
+// Binding powers follow the operator precedence table of jsonata-js
+fn prefix_binding_power(op: &Operator) -> Option<((), u8)> {
     match op {
-        Operator::Minus => ((), 5),
-        _ => panic!("bad op: {:?}", op),
+        Operator::Minus => Some(((), 70)),
+        _ => None,
     }
 }
 
 fn infix_binding_power(op: &Operator) -> Option<(u8, u8)> {
     let res = match op {
-        Operator::Plus | Operator::Minus => (1, 2),
-        Operator::Star | Operator::Slash => (3, 4),
-        Operator::Dot => (6, 5),
+        Operator::Assign => (10, 9),
+        // The branches of a condition and the right side of `?:` and `??` extend as far as possible
+        Operator::Question => (20, 0),
+        Operator::QuestionColon | Operator::QuestionQuestion => (40, 0),
+        Operator::DotDot => (20, 21),
+        Operator::Or => (25, 26),
+        Operator::And => (30, 31),
+        Operator::Equal | Operator::NotEqual
+            | Operator::Less | Operator::LessEqual
+            | Operator::Greater | Operator::GreaterEqual
+            | Operator::In | Operator::Chain => (40, 41),
+        Operator::Plus | Operator::Minus | Operator::Ampersand => (50, 51),
+        Operator::Star | Operator::Slash | Operator::Percentage => (60, 61),
+        Operator::Dot => (76, 75),
         _ => return None,
     };
     Some(res)
 }
 
+fn postfix_binding_power(op: &Operator) -> Option<(u8, ())> {
+    let res = match op {
+        // Below the right binding power of `.`, so a sort applies to the whole path before it
+        Operator::Caret => (74, ()),
+        Operator::BraceLeft => (70, ()),
+        Operator::BracketLeft | Operator::ParenLeft | Operator::At | Operator::Hash => (80, ()),
+        _ => return None,
+    };
+    Some(res)
+}
+
+/// Consumes the next token, which must be the operator `expected`.
+fn expect(lexer: &mut Lexer, expected: Operator) -> Result<()> {
+    match lexer.next() {
+        Some(Ok(Token::Operator(op))) if op == expected => Ok(()),
+        Some(Ok(_)) => Err(Error::S0202(lexer.span().start)),
+        Some(Err(e)) => Err(e),
+        None => Err(Error::S0203(lexer.span().start)),
+    }
+}
+
+/// Parses comma separated expressions up to and including the `close` operator.
+fn list(lexer: &mut Lexer, close: Operator) -> Result<Vec<Expression>> {
+    let mut items = vec![];
+    match lexer.peek() {
+        Some(Ok(Token::Operator(op))) if *op == close => {
+            lexer.next();
+            return Ok(items);
+        },
+        Some(Err(e)) => return Err(e.clone()),
+        _ => {},
+    }
+    loop {
+        items.push(expr_bp(lexer, 0)?);
+        match lexer.next() {
+            Some(Ok(Token::Operator(Operator::Comma))) => continue,
+            Some(Ok(Token::Operator(op))) if op == close => return Ok(items),
+            Some(Ok(_)) => return Err(Error::S0202(lexer.span().start)),
+            Some(Err(e)) => return Err(e),
+            None => return Err(Error::S0203(lexer.span().start)),
+        }
+    }
+}
+
+/// Parses the comma separated arguments of a function call up to and including `)`,
+/// where `?` is a placeholder for partial application.
+fn arguments(lexer: &mut Lexer) -> Result<Vec<Expression>> {
+    let mut args = vec![];
+    match lexer.peek() {
+        Some(Ok(Token::Operator(Operator::ParenRight))) => {
+            lexer.next();
+            return Ok(args);
+        },
+        Some(Err(e)) => return Err(e.clone()),
+        _ => {},
+    }
+    loop {
+        match lexer.next_if(|token| token == &Token::Operator(Operator::Question)) {
+            Some(question) => {
+                question?;
+                args.push(Expression::Placeholder);
+            },
+            None => args.push(expr_bp(lexer, 0)?),
+        }
+        match lexer.next() {
+            Some(Ok(Token::Operator(Operator::Comma))) => continue,
+            Some(Ok(Token::Operator(Operator::ParenRight))) => return Ok(args),
+            Some(Ok(_)) => return Err(Error::S0202(lexer.span().start)),
+            Some(Err(e)) => return Err(e),
+            None => return Err(Error::S0203(lexer.span().start)),
+        }
+    }
+}
+
+/// Parses the comma separated terms of an order-by clause, each optionally prefixed with
+/// `<` for ascending or `>` for descending order, including the parentheses.
+fn sort_terms(lexer: &mut Lexer) -> Result<Vec<(Expression, SortOrder)>> {
+    expect(lexer, Operator::ParenLeft)?;
+    let mut terms = vec![];
+    loop {
+        let order = match lexer.next_if(|token| matches!(token, Token::Operator(Operator::Less | Operator::Greater))) {
+            Some(Ok(Token::Operator(Operator::Greater))) => SortOrder::Descending,
+            Some(Err(e)) => return Err(e),
+            _ => SortOrder::Ascending,
+        };
+        terms.push((expr_bp(lexer, 0)?, order));
+        match lexer.next() {
+            Some(Ok(Token::Operator(Operator::Comma))) => continue,
+            Some(Ok(Token::Operator(Operator::ParenRight))) => return Ok(terms),
+            Some(Ok(_)) => return Err(Error::S0202(lexer.span().start)),
+            Some(Err(e)) => return Err(e),
+            None => return Err(Error::S0203(lexer.span().start)),
+        }
+    }
+}
+
+/// Parses the `;` separated expressions of a block up to and including `)`.
+fn block(lexer: &mut Lexer) -> Result<Vec<Expression>> {
+    let mut expressions = vec![];
+    loop {
+        match lexer.peek() {
+            Some(Ok(Token::Operator(Operator::ParenRight))) => {
+                lexer.next();
+                return Ok(expressions);
+            },
+            Some(Err(e)) => return Err(e.clone()),
+            _ => {},
+        }
+        expressions.push(expr_bp(lexer, 0)?);
+        match lexer.next() {
+            Some(Ok(Token::Operator(Operator::Semicolon))) => continue,
+            Some(Ok(Token::Operator(Operator::ParenRight))) => return Ok(expressions),
+            Some(Ok(_)) => return Err(Error::S0202(lexer.span().start)),
+            Some(Err(e)) => return Err(e),
+            None => return Err(Error::S0203(lexer.span().start)),
+        }
+    }
+}
+
+/// Parses the comma separated `key: value` pairs of an object constructor up to and including `}`.
+fn pairs(lexer: &mut Lexer) -> Result<Vec<(Expression, Expression)>> {
+    let mut pairs = vec![];
+    match lexer.peek() {
+        Some(Ok(Token::Operator(Operator::BraceRight))) => {
+            lexer.next();
+            return Ok(pairs);
+        },
+        Some(Err(e)) => return Err(e.clone()),
+        _ => {},
+    }
+    loop {
+        let key = expr_bp(lexer, 0)?;
+        expect(lexer, Operator::Colon)?;
+        let value = expr_bp(lexer, 0)?;
+        pairs.push((key, value));
+        match lexer.next() {
+            Some(Ok(Token::Operator(Operator::Comma))) => continue,
+            Some(Ok(Token::Operator(Operator::BraceRight))) => return Ok(pairs),
+            Some(Ok(_)) => return Err(Error::S0202(lexer.span().start)),
+            Some(Err(e)) => return Err(e),
+            None => return Err(Error::S0203(lexer.span().start)),
+        }
+    }
+}
+
+/// Parses a function definition following the `function` keyword: the parameters,
+/// an optional signature and the body.
+fn lambda(lexer: &mut Lexer) -> Result<Expression> {
+    expect(lexer, Operator::ParenLeft)?;
+    let mut params = vec![];
+    if lexer.next_if(|token| token == &Token::Operator(Operator::ParenRight)).is_none() {
+        loop {
+            match lexer.next() {
+                Some(Ok(Token::Variable(name))) => params.push(name.to_string()),
+                Some(Ok(_)) => return Err(Error::S0208(lexer.span().start)),
+                Some(Err(e)) => return Err(e),
+                None => return Err(Error::S0203(lexer.span().start)),
+            }
+            match lexer.next() {
+                Some(Ok(Token::Operator(Operator::Comma))) => continue,
+                Some(Ok(Token::Operator(Operator::ParenRight))) => break,
+                Some(Ok(_)) => return Err(Error::S0202(lexer.span().start)),
+                Some(Err(e)) => return Err(e),
+                None => return Err(Error::S0203(lexer.span().start)),
+            }
+        }
+    }
+
+    let signature = match lexer.next_if(|token| token == &Token::Operator(Operator::Less)) {
+        Some(less) => {
+            less?;
+            let start = lexer.span().start;
+            let mut depth = 1;
+            while depth > 0 {
+                match lexer.next() {
+                    Some(Ok(Token::Operator(Operator::Less))) => depth += 1,
+                    Some(Ok(Token::Operator(Operator::Greater))) => depth -= 1,
+                    Some(Ok(Token::Operator(Operator::BraceLeft))) => return Err(Error::S0202(lexer.span().start)),
+                    Some(Ok(_)) => {},
+                    Some(Err(e)) => return Err(e),
+                    None => return Err(Error::S0203(lexer.span().start)),
+                }
+            }
+            let end = lexer.span().end;
+            Some(lexer.text(Span { start, end }).to_string())
+        },
+        None => None,
+    };
+
+    expect(lexer, Operator::BraceLeft)?;
+    let body = expr_bp(lexer, 0)?;
+    expect(lexer, Operator::BraceRight)?;
+    Ok(Expression::Lambda { params, signature, body: Rc::new(body) })
+}
+
 fn expr_bp(lexer: &mut Lexer, min_bp: u8) -> Result<Expression> {
-    let lhs = match lexer.next() {
+    let lhs = match lexer.next_operand() {
         Some(token) => token?,
         None => return Ok(Expression::Atom(Atom::End)),
     };
 
     let mut lhs = match lhs {
         Token::Number(n) => Expression::Atom(Atom::Number(n)),
+        Token::Name("function" | "λ") if matches!(lexer.peek(), Some(Ok(Token::Operator(Operator::ParenLeft)))) => lambda(lexer)?,
         Token::Name(n) => Expression::Atom(Atom::Name(n.to_string())),
         Token::Variable(name) => {
-            if let Some(_) = lexer.next_if(|token| token == &Token::Operator(Operator::ParenLeft)) {
-                let mut  args: Vec<Expression> = vec![];
-                loop {
-                    match lexer.peek().expect("End of file todo: return error") {
-                        Ok(token) => {
-                            if token == &Token::Operator(Operator::ParenRight) {
-                                lexer.next(); // consume left paren
-                                break;
-                            } else {
-                                let arg = expr_bp(lexer, 0)?;
-                                args.push(arg);
-                            }
-                        },
-                        Err(e) => return Err(e.clone()),
-                    }
-                };
+            if lexer.next_if(|token| token == &Token::Operator(Operator::ParenLeft)).is_some() {
+                let args = arguments(lexer)?;
                 Expression::Variable(name.to_string(), Variable::Function(args))
             } else {
                 Expression::Variable(name.to_string(), Variable::Value)
             }
         }
-        Token::String(n) => Expression::Atom(Atom::String(n.to_string())),
+        Token::String(n) => Expression::Atom(Atom::String(n.into_owned())),
+        Token::Bool(b) => Expression::Atom(Atom::Bool(b)),
+        Token::Null => Expression::Atom(Atom::Null),
+        Token::Regex { pattern, flags } => Expression::Atom(Atom::Regex { pattern: pattern.to_string(), flags: flags.to_string() }),
+        Token::Context => Expression::Context,
+        Token::Root => Expression::Root,
         Token::Operator(Operator::ParenLeft) => {
-            let lhs = expr_bp(lexer, 0)?;
-            assert_eq!(
-                lexer.next(),
-                Some(Ok(Token::Operator(Operator::ParenRight)))
-            );
-            lhs
+            let mut expressions = block(lexer)?;
+            // A single expression in parentheses only groups, unless it binds a variable
+            match expressions.pop() {
+                Some(expression) if expressions.is_empty() && !matches!(expression, Expression::Assign(..)) => expression,
+                Some(expression) => {
+                    expressions.push(expression);
+                    Expression::Block(expressions)
+                },
+                None => Expression::Block(expressions),
+            }
         }
+        // In operand position `*` is a wildcard step rather than multiplication
+        Token::Operator(Operator::Star) => Expression::Wildcard,
+        Token::Operator(Operator::StarStar) => Expression::Descendant,
+        // In operand position `%` is the parent operator rather than modulo
+        Token::Operator(Operator::Percentage) => Expression::Parent(lexer.span().start.offset),
+        Token::Operator(Operator::Pipe) => {
+            let pattern = expr_bp(lexer, 0)?;
+            expect(lexer, Operator::Pipe)?;
+            let update = expr_bp(lexer, 0)?;
+            let delete = match lexer.next_if(|token| token == &Token::Operator(Operator::Comma)) {
+                Some(comma) => {
+                    comma?;
+                    Some(Rc::new(expr_bp(lexer, 0)?))
+                },
+                None => None,
+            };
+            expect(lexer, Operator::Pipe)?;
+            Expression::Transform { pattern: Rc::new(pattern), update: Rc::new(update), delete }
+        },
+        Token::Operator(Operator::BracketLeft) => Expression::ArrayConstructor(list(lexer, Operator::BracketRight)?),
+        Token::Operator(Operator::BraceLeft) => Expression::ObjectConstructor(pairs(lexer)?),
+        Token::Comment(_) => unreachable!("the parser's lexer skips comments"),
         Token::Operator(op) => {
-            let ((), r_bp) = prefix_binding_power(&op);
+            let ((), r_bp) = prefix_binding_power(&op).ok_or(Error::S0211(lexer.span().start))?;
             let rhs = expr_bp(lexer, r_bp)?;
             Expression::Unary(NumericUnaryOperator::Negate, Box::new(rhs))
         }
@@ -74,16 +300,49 @@ fn expr_bp(lexer: &mut Lexer, min_bp: u8) -> Result<Expression> {
         let op = match lexer.peek().cloned() {
             Some(Ok(Token::Operator(op))) => op,
             Some(Err(e)) => return Err(e),
-            None => break,
-            t => panic!("Unexpected token: {:?}", t),
+            Some(Ok(_)) | None => break,
         };
 
+        if let Some((l_bp, ())) = postfix_binding_power(&op) {
+            if l_bp < min_bp {
+                break;
+            }
+
+            lexer.next();
+
+            lhs = match op {
+                Operator::BracketLeft => {
+                    let rhs = expr_bp(lexer, 0)?;
+                    expect(lexer, Operator::BracketRight)?;
+                    Expression::Predicate(Box::new(lhs), Box::new(rhs))
+                },
+                Operator::ParenLeft => Expression::Call(Box::new(lhs), arguments(lexer)?),
+                Operator::BraceLeft => Expression::GroupBy(Box::new(lhs), pairs(lexer)?),
+                Operator::Caret => Expression::OrderBy(Box::new(lhs), sort_terms(lexer)?),
+                Operator::At | Operator::Hash => {
+                    let name = match lexer.next() {
+                        Some(Ok(Token::Variable(name))) => name.to_string(),
+                        Some(Ok(_)) => return Err(Error::S0214(lexer.span().start)),
+                        Some(Err(e)) => return Err(e),
+                        None => return Err(Error::S0203(lexer.span().start)),
+                    };
+                    match op {
+                        Operator::At => Expression::Focus(Box::new(lhs), name),
+                        _ => Expression::Index(Box::new(lhs), name),
+                    }
+                },
+                op => unreachable!("Operator without postfix binding power: {op}"),
+            };
+            continue;
+        }
+
         if let Some((l_bp, r_bp)) = infix_binding_power(&op) {
             if l_bp < min_bp {
                 break;
             }
 
             lexer.next();
+            let position = lexer.span().start;
 
             let rhs = expr_bp(lexer, r_bp)?;
             lhs = match op {
@@ -93,9 +352,36 @@ fn expr_bp(lexer: &mut Lexer, min_bp: u8) -> Result<Expression> {
                 crate::token::Operator::Slash => Expression::BinaryNumeric(NumericBinaryOperator::Div, Box::new(lhs), Box::new(rhs)),
                 crate::token::Operator::Percentage => Expression::BinaryNumeric(NumericBinaryOperator::Mod, Box::new(lhs), Box::new(rhs)),
                 crate::token::Operator::Dot => Expression::Path(Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::And => Expression::BinaryBoolean(BooleanBinaryOperator::And, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::Or => Expression::BinaryBoolean(BooleanBinaryOperator::Or, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::Equal => Expression::Comparison(ComparisonOperator::Equal, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::NotEqual => Expression::Comparison(ComparisonOperator::NotEqual, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::Less => Expression::Comparison(ComparisonOperator::Less, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::LessEqual => Expression::Comparison(ComparisonOperator::LessEqual, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::Greater => Expression::Comparison(ComparisonOperator::Greater, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::GreaterEqual => Expression::Comparison(ComparisonOperator::GreaterEqual, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::In => Expression::Comparison(ComparisonOperator::In, Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::Assign => match lhs {
+                    Expression::Variable(name, Variable::Value) => Expression::Assign(name, Box::new(rhs)),
+                    _ => return Err(Error::S0212(position)),
+                },
+                crate::token::Operator::Question => {
+                    let otherwise = match lexer.next_if(|token| token == &Token::Operator(Operator::Colon)) {
+                        Some(colon) => {
+                            colon?;
+                            Some(Box::new(expr_bp(lexer, 0)?))
+                        },
+                        None => None,
+                    };
+                    Expression::Condition(Box::new(lhs), Box::new(rhs), otherwise)
+                },
+                crate::token::Operator::QuestionColon => Expression::Default(Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::QuestionQuestion => Expression::Coalesce(Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::Chain => Expression::Chain(Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::Ampersand => Expression::Concatenate(Box::new(lhs), Box::new(rhs)),
+                crate::token::Operator::DotDot => Expression::Range(Box::new(lhs), Box::new(rhs)),
 
-                crate::token::Operator::ParenRight => todo!(),
-                crate::token::Operator::ParenLeft => todo!(),
+                op => unreachable!("Operator without infix binding power: {op}"),
             };
             continue;
         }
@@ -105,8 +391,86 @@ fn expr_bp(lexer: &mut Lexer, min_bp: u8) -> Result<Expression> {
     Ok(lhs)
 }
 
+/// Goes back one path step for a parent operator at `offset` that is `level` steps from its
+/// parent, returning what is left to go back, if anything.
+fn seek_parent(step: &Expression, (offset, level): (usize, usize), lexer: &Lexer) -> Result<Option<(usize, usize)>> {
+    match step {
+        Expression::Atom(Atom::Name(_)) | Expression::Wildcard if level == 1 => Ok(None),
+        Expression::Atom(Atom::Name(_)) | Expression::Wildcard => Ok(Some((offset, level - 1))),
+        Expression::Parent(_) => Ok(Some((offset, level + 1))),
+        Expression::Predicate(step, _) | Expression::Index(step, _) | Expression::Focus(step, _) => seek_parent(step, (offset, level), lexer),
+        _ => Err(Error::S0217(lexer.locate(offset))),
+    }
+}
+
+/// The parent operators in `expr` that refer beyond it, as their offset and the number of
+/// steps they still have to go back. A parent that can't be derived is an `S0217` error.
+fn seeking_parent(expr: &Expression, lexer: &Lexer) -> Result<Vec<(usize, usize)>> {
+    let mut seeking = vec![];
+    // Expressions that are evaluated against the results of a path step
+    let (step, per_item): (Option<&Expression>, Vec<&Expression>) = match expr {
+        Expression::Parent(offset) => return Ok(vec![(*offset, 1)]),
+        Expression::Path(step, rhs) | Expression::Predicate(step, rhs) => (Some(step), vec![rhs]),
+        Expression::GroupBy(step, pairs) => (Some(step), pairs.iter().flat_map(|(key, value)| [key, value]).collect()),
+        Expression::OrderBy(step, terms) => (Some(step), terms.iter().map(|(term, _)| term).collect()),
+        _ => (None, vec![]),
+    };
+    if let Some(step) = step {
+        seeking.extend(seeking_parent(step, lexer)?);
+        for expr in per_item {
+            for parent in seeking_parent(expr, lexer)? {
+                seeking.extend(seek_parent(step, parent, lexer)?);
+            }
+        }
+        return Ok(seeking);
+    }
+
+    // Everything else is evaluated in the context of `expr` itself
+    let children: Vec<&Expression> = match expr {
+        Expression::BinaryNumeric(_, lhs, rhs)
+            | Expression::BinaryBoolean(_, lhs, rhs)
+            | Expression::Comparison(_, lhs, rhs)
+            | Expression::Concatenate(lhs, rhs)
+            | Expression::Default(lhs, rhs)
+            | Expression::Coalesce(lhs, rhs)
+            | Expression::Chain(lhs, rhs)
+            | Expression::Range(lhs, rhs) => vec![lhs, rhs],
+        Expression::Condition(condition, then, otherwise) => {
+            [condition, then].into_iter().chain(otherwise).map(|expr| &**expr).collect()
+        },
+        Expression::Unary(_, rhs)
+            | Expression::Assign(_, rhs)
+            | Expression::Focus(rhs, _)
+            | Expression::Index(rhs, _) => vec![rhs],
+        Expression::ArrayConstructor(items)
+            | Expression::Block(items)
+            | Expression::Variable(_, Variable::Function(items)) => items.iter().collect(),
+        Expression::Call(callee, args) => [&**callee].into_iter().chain(args).collect(),
+        Expression::ObjectConstructor(pairs) => pairs.iter().flat_map(|(key, value)| [key, value]).collect(),
+        Expression::Lambda { body, .. } => vec![body],
+        Expression::Transform { pattern, update, delete } => {
+            [pattern, update].into_iter().chain(delete).map(|expr| &**expr).collect()
+        },
+        _ => vec![],
+    };
+    for child in children {
+        seeking.extend(seeking_parent(child, lexer)?);
+    }
+    Ok(seeking)
+}
+
 pub fn parse(mut lexer: Lexer) -> Result<Expression> {
-    expr_bp(&mut lexer, 0)
+    let expression = expr_bp(&mut lexer, 0)?;
+    match lexer.next() {
+        Some(Ok(_)) => return Err(Error::S0201(lexer.span().start)),
+        Some(Err(e)) => return Err(e),
+        None => {},
+    }
+    // A parent operator needs a path step before it to navigate back over
+    match seeking_parent(&expression, &lexer)?.first() {
+        Some((offset, _)) => Err(Error::S0217(lexer.locate(*offset))),
+        None => Ok(expression),
+    }
 }
 
 #[cfg(test)]
@@ -114,6 +478,7 @@ mod tests {
     use super::parse;
     use crate::Lexer;
     use crate::Result;
+    use jsonata_error::{Error, Position};
 
     #[test]
     fn test_parse_variable_function_args() -> Result<()> {
@@ -129,6 +494,42 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_lambda() -> Result<()> {
+        let r = parse(Lexer::new("function($x, $y) { $x + $y }"))?;
+        assert_eq!(r.to_string(), "(function ($x $y) (+ $x $y))");
+
+        let r = parse(Lexer::new("λ() { 1 }"))?;
+        assert_eq!(r.to_string(), "(function () 1)");
+
+        let r = parse(Lexer::new("$map(items, function($v) { $v * 2 })"))?;
+        assert_eq!(r.to_string(), "$map(items, (function ($v) (* $v 2)))");
+
+        // Without parentheses `function` is an ordinary name
+        let r = parse(Lexer::new("function.name"))?;
+        assert_eq!(r.to_string(), "(. function name)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lambda_signature() -> Result<()> {
+        let r = parse(Lexer::new("function($x, $y)<n-n:n> { $x + $y }"))?;
+        assert_eq!(r.to_string(), "(function ($x $y) <n-n:n> (+ $x $y))");
+
+        let r = parse(Lexer::new("function($a)<a<n>?:a<n>> { $a }"))?;
+        assert_eq!(r.to_string(), "(function ($a) <a<n>?:a<n>> $a)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lambda_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("function($x, y) { y }")).unwrap_err(), Error::S0208(position(13, 1, 14)));
+        assert_eq!(parse(Lexer::new("function($x) $x")).unwrap_err(), Error::S0202(position(13, 1, 14)));
+        assert_eq!(parse(Lexer::new("function($x)<n:n { $x }")).unwrap_err(), Error::S0202(position(17, 1, 18)));
+        assert_eq!(parse(Lexer::new("function($x) { $x")).unwrap_err(), Error::S0203(position(17, 1, 18)));
+    }
+
     #[test]
     fn test_parse_variable_value() -> Result<()> {
         let r = parse(Lexer::new("$a"))?;
@@ -136,6 +537,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_context_and_root() -> Result<()> {
+        let r = parse(Lexer::new("$.a * $$.b"))?;
+        assert_eq!(r.to_string(), "(* (. $ a) (. $$ b))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_regex() -> Result<()> {
+        let r = parse(Lexer::new("$match(/ab+c/i)"))?;
+        assert_eq!(r.to_string(), "$match(/ab+c/i)");
+
+        let r = parse(Lexer::new("a / b"))?;
+        assert_eq!(r.to_string(), "(/ a b)");
+
+        let r = parse(Lexer::new("/a/ / (b) / -/c/"))?;
+        assert_eq!(r.to_string(), "(/ (/ /a/ b) (- /c/))");
+        Ok(())
+    }
+
     #[test]
     fn test_parse_parenthesised_expression() -> Result<()> {
         let lexer = Lexer::new("(((0)))");
@@ -182,9 +603,334 @@ mod tests {
         let lexer = Lexer::new("price.\"my name\".bar");
         let r = parse(lexer)?;
         assert_eq!(r.to_string(), "(. price (. \"my name\" bar))");
+
+        let lexer = Lexer::new("price.`my-name`.bar");
+        let r = parse(lexer)?;
+        assert_eq!(r.to_string(), "(. price (. my-name bar))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_comments() -> Result<()> {
+        let lexer = Lexer::new("/* sum */ 1 + /* product */ 2 * 3");
+        let r = parse(lexer)?;
+        assert_eq!(r.to_string(), "(+ 1 (* 2 3))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_boolean_expression() -> Result<()> {
+        let lexer = Lexer::new("a or b and c");
+        let r = parse(lexer)?;
+        assert_eq!(r.to_string(), "(or a (and b c))");
+
+        let lexer = Lexer::new("true and x.y or null");
+        let r = parse(lexer)?;
+        assert_eq!(r.to_string(), "(or (and true (. x y)) null)");
+
+        let lexer = Lexer::new("a in b or c + 1 in d");
+        let r = parse(lexer)?;
+        assert_eq!(r.to_string(), "(or (in a b) (in (+ c 1) d))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error_positions() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("(1 + 2")).unwrap_err(), Error::S0203(position(6, 1, 7)));
+        assert_eq!(parse(Lexer::new("(1 + 2 3")).unwrap_err(), Error::S0202(position(7, 1, 8)));
+        assert_eq!(parse(Lexer::new("$f(a.b")).unwrap_err(), Error::S0203(position(6, 1, 7)));
+        assert_eq!(parse(Lexer::new("1 +\n  = 2")).unwrap_err(), Error::S0211(position(6, 2, 3)));
+        assert_eq!(parse(Lexer::new("a b")).unwrap_err(), Error::S0201(position(2, 1, 3)));
+        assert_eq!(parse(Lexer::new("a + \"b")).unwrap_err(), Error::S0101(position(4, 1, 5)));
+    }
+
+    #[test]
+    fn test_parse_comparison() -> Result<()> {
+        let r = parse(Lexer::new("a + 1 > b * 2 and c != \"x\" or d in e"))?;
+        assert_eq!(r.to_string(), "(or (and (> (+ a 1) (* b 2)) (!= c \"x\")) (in d e))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_predicate() -> Result<()> {
+        let r = parse(Lexer::new("Order[Price > 10]"))?;
+        assert_eq!(r.to_string(), "([] Order (> Price 10))");
+
+        let r = parse(Lexer::new("Account.Phone[0].number"))?;
+        assert_eq!(r.to_string(), "(. Account (. ([] Phone 0) number))");
+
+        let r = parse(Lexer::new("Phone[-1][type = \"home\"]"))?;
+        assert_eq!(r.to_string(), "([] ([] Phone (- 1)) (= type \"home\"))");
+
+        let r = parse(Lexer::new("-a[0]"))?;
+        assert_eq!(r.to_string(), "(- ([] a 0))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_predicate_unterminated() {
+        let position = Position { offset: 10, line: 1, column: 11 };
+        assert_eq!(parse(Lexer::new("Order[qty ")).unwrap_err(), Error::S0203(position));
+    }
+
+    #[test]
+    fn test_parse_array_constructor() -> Result<()> {
+        let r = parse(Lexer::new("[]"))?;
+        assert_eq!(r.to_string(), "[]");
+
+        let r = parse(Lexer::new("[1, 2 + 3, [a.b, \"x\"]]"))?;
+        assert_eq!(r.to_string(), "[1, (+ 2 3), [(. a b), \"x\"]]");
+
+        let r = parse(Lexer::new("[1..$count, 0][1]"))?;
+        assert_eq!(r.to_string(), "([] [(.. 1 $count), 0] 1)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_array_constructor_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("[1, 2")).unwrap_err(), Error::S0203(position(5, 1, 6)));
+        assert_eq!(parse(Lexer::new("[1 2]")).unwrap_err(), Error::S0202(position(3, 1, 4)));
+    }
+
+    #[test]
+    fn test_parse_object_constructor() -> Result<()> {
+        let r = parse(Lexer::new("{}"))?;
+        assert_eq!(r.to_string(), "{}");
+
+        let r = parse(Lexer::new("{ \"name\": FirstName & \" \" & Surname, \"total\": $sum(Price) }"))?;
+        assert_eq!(r.to_string(), "{\"name\": (& (& FirstName \" \") Surname), \"total\": $sum(Price)}");
+
+        let r = parse(Lexer::new("{type: {\"n\": [1]}}"))?;
+        assert_eq!(r.to_string(), "{type: {\"n\": [1]}}");
         Ok(())
     }
 
+    #[test]
+    fn test_parse_group_by() -> Result<()> {
+        let r = parse(Lexer::new("Account.Order.Product{ SKU: $sum(Price) }"))?;
+        assert_eq!(r.to_string(), "({} (. Account (. Order Product)) {SKU: $sum(Price)})");
+
+        let r = parse(Lexer::new("Account.Order.Product.{ SKU: Price }"))?;
+        assert_eq!(r.to_string(), "(. Account (. Order (. Product {SKU: Price})))");
+
+        let r = parse(Lexer::new("Product[0]{ SKU: Price }"))?;
+        assert_eq!(r.to_string(), "({} ([] Product 0) {SKU: Price})");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_object_constructor_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("{\"a\" 1}")).unwrap_err(), Error::S0202(position(5, 1, 6)));
+        assert_eq!(parse(Lexer::new("{\"a\": 1")).unwrap_err(), Error::S0203(position(7, 1, 8)));
+    }
+
+    #[test]
+    fn test_parse_condition() -> Result<()> {
+        let r = parse(Lexer::new("a > 1 ? \"big\" : \"small\""))?;
+        assert_eq!(r.to_string(), "(? (> a 1) \"big\" \"small\")");
+
+        let r = parse(Lexer::new("a ? b"))?;
+        assert_eq!(r.to_string(), "(? a b)");
+
+        let r = parse(Lexer::new("a ? b ? 1 : 2 : c or d"))?;
+        assert_eq!(r.to_string(), "(? a (? b 1 2) (or c d))");
+
+        let r = parse(Lexer::new("{\"k\": a ? 1 : 2}"))?;
+        assert_eq!(r.to_string(), "{\"k\": (? a 1 2)}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_default_and_coalesce() -> Result<()> {
+        let r = parse(Lexer::new("a.b ?: c + 1"))?;
+        assert_eq!(r.to_string(), "(?: (. a b) (+ c 1))");
+
+        let r = parse(Lexer::new("1 + a ?? b ?? c"))?;
+        assert_eq!(r.to_string(), "(?? (+ 1 a) (?? b c))");
+
+        let r = parse(Lexer::new("x or a ?? b"))?;
+        assert_eq!(r.to_string(), "(or x (?? a b))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_block() -> Result<()> {
+        let r = parse(Lexer::new("( $rate := 0.2; $net := Price * (1 - $rate); $net )"))?;
+        assert_eq!(r.to_string(), "(; (:= $rate 0.2) (:= $net (* Price (- 1 $rate))) $net)");
+
+        let r = parse(Lexer::new("($a := $b := 1;)"))?;
+        assert_eq!(r.to_string(), "(; (:= $a (:= $b 1)))");
+
+        let r = parse(Lexer::new("()"))?;
+        assert_eq!(r.to_string(), "(;)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_block_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("a := 1")).unwrap_err(), Error::S0212(position(2, 1, 3)));
+        assert_eq!(parse(Lexer::new("(1; 2")).unwrap_err(), Error::S0203(position(5, 1, 6)));
+        assert_eq!(parse(Lexer::new("(1; 2]")).unwrap_err(), Error::S0202(position(5, 1, 6)));
+    }
+
+    #[test]
+    fn test_parse_chain() -> Result<()> {
+        let r = parse(Lexer::new("Name ~> $trim() ~> $substring(3)"))?;
+        assert_eq!(r.to_string(), "(~> (~> Name $trim()) $substring(3))");
+
+        let r = parse(Lexer::new("a + b ~> $string = \"3\""))?;
+        assert_eq!(r.to_string(), "(= (~> (+ a b) $string) \"3\")");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_order_by() -> Result<()> {
+        let r = parse(Lexer::new("Account.Order^(>Price, <Name)"))?;
+        assert_eq!(r.to_string(), "(^ (. Account Order) >Price <Name)");
+
+        let r = parse(Lexer::new("Account.Order^(Price * Quantity).Product[0]"))?;
+        assert_eq!(r.to_string(), "(. (^ (. Account Order) <(* Price Quantity)) ([] Product 0))");
+
+        let r = parse(Lexer::new("a = b^(c)"))?;
+        assert_eq!(r.to_string(), "(= a (^ b <c))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_order_by_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("a^b")).unwrap_err(), Error::S0202(position(2, 1, 3)));
+        assert_eq!(parse(Lexer::new("a^(b c)")).unwrap_err(), Error::S0202(position(5, 1, 6)));
+    }
+
+    #[test]
+    fn test_parse_wildcard() -> Result<()> {
+        let r = parse(Lexer::new("Address.*"))?;
+        assert_eq!(r.to_string(), "(. Address *)");
+
+        let r = parse(Lexer::new("*.Price * 2"))?;
+        assert_eq!(r.to_string(), "(* (. * Price) 2)");
+
+        let r = parse(Lexer::new("**.Postcode"))?;
+        assert_eq!(r.to_string(), "(. ** Postcode)");
+
+        let r = parse(Lexer::new("a.**[Postcode]"))?;
+        assert_eq!(r.to_string(), "(. a ([] ** Postcode))");
+
+        // A slash after a wildcard step divides
+        let r = parse(Lexer::new("Address.* / 2"))?;
+        assert_eq!(r.to_string(), "(/ (. Address *) 2)");
+
+        let r = parse(Lexer::new("** / *"))?;
+        assert_eq!(r.to_string(), "(/ ** *)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_parent() -> Result<()> {
+        let r = parse(Lexer::new("Account.Order.Product.{ \"order\": %.OrderID }"))?;
+        assert_eq!(r.to_string(), "(. Account (. Order (. Product {\"order\": (. % OrderID)})))");
+
+        let r = parse(Lexer::new("a.b.%.%.c"))?;
+        assert_eq!(r.to_string(), "(. a (. b (. % (. % c))))");
+
+        let r = parse(Lexer::new("a.b[%.c = 1] % 2"))?;
+        assert_eq!(r.to_string(), "(% (. a ([] b (= (. % c) 1))) 2)");
+
+        let r = parse(Lexer::new("a@$x.%"))?;
+        assert_eq!(r.to_string(), "(. (@ a $x) %)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_parent_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("%")).unwrap_err(), Error::S0217(position(0, 1, 1)));
+        assert_eq!(parse(Lexer::new("a.%.%")).unwrap_err(), Error::S0217(position(4, 1, 5)));
+        assert_eq!(parse(Lexer::new("$f().%")).unwrap_err(), Error::S0217(position(5, 1, 6)));
+        assert_eq!(parse(Lexer::new("a.{ \"b\": [1].% }")).unwrap_err(), Error::S0217(position(13, 1, 14)));
+    }
+
+    #[test]
+    fn test_parse_focus_and_index() -> Result<()> {
+        let r = parse(Lexer::new("Order#$i.{ \"line\": $i + 1 }"))?;
+        assert_eq!(r.to_string(), "(. (# Order $i) {\"line\": (+ $i 1)})");
+
+        let r = parse(Lexer::new("loans@$l.books@$b[$l.isbn = $b.isbn].title"))?;
+        assert_eq!(r.to_string(), "(. (@ loans $l) (. ([] (@ books $b) (= (. $l isbn) (. $b isbn))) title))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_focus_and_index_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("a@b")).unwrap_err(), Error::S0214(position(2, 1, 3)));
+        assert_eq!(parse(Lexer::new("a#$i b")).unwrap_err(), Error::S0201(position(5, 1, 6)));
+        assert_eq!(parse(Lexer::new("a#")).unwrap_err(), Error::S0203(position(2, 1, 3)));
+    }
+
+    #[test]
+    fn test_parse_transform() -> Result<()> {
+        let r = parse(Lexer::new("$ ~> | Account.Order | {\"status\": \"shipped\"}, [\"draft\"] |"))?;
+        assert_eq!(r.to_string(), "(~> $ (| (. Account Order) {\"status\": \"shipped\"} [\"draft\"]))");
+
+        let r = parse(Lexer::new("|Order[Price > 5]|{\"big\": true}|"))?;
+        assert_eq!(r.to_string(), "(| ([] Order (> Price 5)) {\"big\": true})");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_transform_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("| a | b")).unwrap_err(), Error::S0203(position(7, 1, 8)));
+        assert_eq!(parse(Lexer::new("| a , b |")).unwrap_err(), Error::S0202(position(4, 1, 5)));
+    }
+
+    #[test]
+    fn test_parse_partial_application() -> Result<()> {
+        let r = parse(Lexer::new("$substring(?, 0, 5)"))?;
+        assert_eq!(r.to_string(), "$substring(?, 0, 5)");
+
+        let r = parse(Lexer::new("$map(names, $f(?, ?))"))?;
+        assert_eq!(r.to_string(), "$map(names, $f(?, ?))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_partial_application_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("$f(? + 1)")).unwrap_err(), Error::S0202(position(5, 1, 6)));
+        assert_eq!(parse(Lexer::new("[?]")).unwrap_err(), Error::S0211(position(1, 1, 2)));
+    }
+
+    #[test]
+    fn test_parse_call() -> Result<()> {
+        let r = parse(Lexer::new("$substring(Name, 0, 3)"))?;
+        assert_eq!(r.to_string(), "$substring(Name, 0, 3)");
+
+        let r = parse(Lexer::new("$lookup(fns, \"f\")(x)"))?;
+        assert_eq!(r.to_string(), "$lookup(fns, \"f\")(x)");
+
+        let r = parse(Lexer::new("function($x) { $x * 2 }(3)"))?;
+        assert_eq!(r.to_string(), "(function ($x) (* $x 2))(3)");
+
+        let r = parse(Lexer::new("$f(1)(2)(?).a"))?;
+        assert_eq!(r.to_string(), "(. $f(1)(2)(?) a)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_call_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(parse(Lexer::new("$f(1, 2")).unwrap_err(), Error::S0203(position(7, 1, 8)));
+        assert_eq!(parse(Lexer::new("$f(1 2)")).unwrap_err(), Error::S0202(position(5, 1, 6)));
+        assert_eq!(parse(Lexer::new("$f(1)(2;")).unwrap_err(), Error::S0202(position(7, 1, 8)));
+    }
+
     #[test]
     fn test_parse_numeric_expression() -> Result<()> {
         let lexer = Lexer::new("1");
@@ -1,3 +1,5 @@
+use jsonata_error::Position;
+use std::borrow::Cow;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
@@ -9,6 +11,34 @@ pub enum Operator {
     ParenLeft,
     Minus,
     Percentage,
+    And,
+    Or,
+    In,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Ampersand,
+    Question,
+    Colon,
+    Assign,
+    Chain,
+    DotDot,
+    BracketLeft,
+    BracketRight,
+    BraceLeft,
+    BraceRight,
+    Comma,
+    Semicolon,
+    Pipe,
+    At,
+    Hash,
+    Caret,
+    StarStar,
+    QuestionQuestion,
+    QuestionColon,
 }
 
 impl std::fmt::Display for Operator {
@@ -22,16 +52,63 @@ impl std::fmt::Display for Operator {
             Operator::Dot => write!(f, "."),
             Operator::ParenRight => write!(f, ")"),
             Operator::ParenLeft => write!(f, "("),
+            Operator::And => write!(f, "and"),
+            Operator::Or => write!(f, "or"),
+            Operator::In => write!(f, "in"),
+            Operator::Equal => write!(f, "="),
+            Operator::NotEqual => write!(f, "!="),
+            Operator::Less => write!(f, "<"),
+            Operator::LessEqual => write!(f, "<="),
+            Operator::Greater => write!(f, ">"),
+            Operator::GreaterEqual => write!(f, ">="),
+            Operator::Ampersand => write!(f, "&"),
+            Operator::Question => write!(f, "?"),
+            Operator::Colon => write!(f, ":"),
+            Operator::Assign => write!(f, ":="),
+            Operator::Chain => write!(f, "~>"),
+            Operator::DotDot => write!(f, ".."),
+            Operator::BracketLeft => write!(f, "["),
+            Operator::BracketRight => write!(f, "]"),
+            Operator::BraceLeft => write!(f, "{{"),
+            Operator::BraceRight => write!(f, "}}"),
+            Operator::Comma => write!(f, ","),
+            Operator::Semicolon => write!(f, ";"),
+            Operator::Pipe => write!(f, "|"),
+            Operator::At => write!(f, "@"),
+            Operator::Hash => write!(f, "#"),
+            Operator::Caret => write!(f, "^"),
+            Operator::StarStar => write!(f, "**"),
+            Operator::QuestionQuestion => write!(f, "??"),
+            Operator::QuestionColon => write!(f, "?:"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token<'a> {
  Operator(Operator), // todo use enum Operator?
- String(&'a str),
+ String(Cow<'a, str>), // Borrowed unless the literal contains escape sequences
  Name(&'a str), // todo use enum Name?
  Number(f64), // This should be equal to javascript "Number" (IEEE 754-2019 binary64)
  Variable(&'a str), 
+ Context, // `$`, the current context item
+ Root, // `$$`, the input document
+ Bool(bool),
+ Null,
+ Regex { pattern: &'a str, flags: &'a str },
+ Comment(&'a str), // Only produced when the lexer is asked to keep comments
 }
 
+
+/// The region of the source a token was read from, `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken<'a> {
+    pub token: Token<'a>,
+    pub span: Span,
+}